@@ -1,14 +1,274 @@
+use std::fmt;
+
 use crate::hash::{hash160, hash256};
 use crate::keys::Keychain;
+use crate::params::ChainParams;
+use crate::script::Script;
 
-pub fn from_keychain(keychain: &Keychain) -> String {
+pub fn from_keychain(keychain: &Keychain, params: &ChainParams) -> String {
     let mut payload = hash160(&keychain.public_key_bytes());
 
-    // Base58Check prefix version: 0x00 for addresses.
-    payload.insert(0, 0x00);
+    // Base58Check prefix version: 0x00 for mainnet addresses, 0x6f for the test networks.
+    payload.insert(0, params.pubkey_address_prefix);
 
     let checksum = &hash256(&payload)[0..4];
     payload.extend(checksum);
 
     bs58::encode(payload).into_string()
 }
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum Error {
+    Base58,
+    BadChecksum,
+    /// The Base58Check version isn't a P2PKH or P2SH prefix of the network.
+    UnknownVersion(u8),
+    /// The bech32 human readable part isn't the one of the network.
+    WrongHrp(String),
+    Bech32(&'static str),
+}
+
+/// The scriptPubKey an address pays to: P2PKH and P2SH for Base58Check addresses, or a witness
+/// program for bech32 (segwit v0) and bech32m (segwit v1+) addresses.
+pub fn to_script(address: &str, params: &ChainParams) -> Result<Script, Error> {
+    let hrp_prefix = format!("{}1", params.bech32_hrp);
+
+    if address.to_lowercase().starts_with(&hrp_prefix) {
+        return decode_segwit(address, params)
+    };
+
+    let payload = match bs58::decode(address).into_vec() {
+        Ok(payload) if payload.len() == 25 => payload,
+        // bech32 addresses are never mixed case, unlike base58 ones: maybe one of another network.
+        _ if address.contains('1') && (address.to_lowercase() == address || address.to_uppercase() == address) => {
+            return decode_segwit(address, params)
+        },
+        _ => return Err(Error::Base58),
+    };
+
+    let (payload, checksum) = payload.split_at(21);
+    if hash256(payload)[..4] != *checksum {
+        return Err(Error::BadChecksum)
+    };
+
+    match payload[0] {
+        version if version == params.pubkey_address_prefix => Ok(Script::new_p2pkh(&payload[1..])),
+        version if version == params.script_address_prefix => Ok(Script::new_p2sh(&payload[1..])),
+        version => Err(Error::UnknownVersion(version)),
+    }
+}
+
+// https://github.com/bitcoin/bips/blob/master/bip-0173.mediawiki
+// https://github.com/bitcoin/bips/blob/master/bip-0350.mediawiki
+
+const CHARSET: &[u8] = b"qpzry9x8gf2tvdw0s3jn54khce6mua7l";
+
+const BECH32_CONST: u32 = 1;
+const BECH32M_CONST: u32 = 0x2bc830a3;
+
+fn decode_segwit(address: &str, params: &ChainParams) -> Result<Script, Error> {
+    if address.len() > 90 {
+        return Err(Error::Bech32("too long"))
+    };
+
+    if address.to_lowercase() != address && address.to_uppercase() != address {
+        return Err(Error::Bech32("mixed case"))
+    };
+
+    let address = address.to_lowercase();
+    let (hrp, data) = address.rsplit_once('1').unwrap();
+
+    if hrp != params.bech32_hrp {
+        return Err(Error::WrongHrp(hrp.to_string()))
+    };
+
+    let values = data.bytes()
+        .map(|c| CHARSET.iter().position(|&x| x == c).map(|v| v as u8))
+        .collect::<Option<Vec<u8>>>()
+        .ok_or(Error::Bech32("invalid character"))?;
+
+    if values.len() < 7 {
+        return Err(Error::Bech32("too short"))
+    };
+
+    let checksum_const = polymod(&[hrp_expand(hrp), values.clone()].concat());
+    let (values, _) = values.split_at(values.len() - 6);
+
+    let version = values[0];
+    let program = convert_bits(&values[1..], 5, 8).ok_or(Error::Bech32("invalid padding"))?;
+
+    // segwit v0 uses bech32, later versions use bech32m.
+    match (version, checksum_const) {
+        (0, BECH32_CONST) => {},
+        (1..=16, BECH32M_CONST) => {},
+        (0..=16, _) => return Err(Error::BadChecksum),
+        _ => return Err(Error::Bech32("invalid witness version")),
+    };
+
+    if !(2..=40).contains(&program.len()) || (version == 0 && program.len() != 20 && program.len() != 32) {
+        return Err(Error::Bech32("invalid witness program length"))
+    };
+
+    Ok(Script::new_witness_program(version, &program))
+}
+
+fn polymod(values: &[u8]) -> u32 {
+    const GENERATOR: [u32; 5] = [0x3b6a57b2, 0x26508e6d, 0x1ea119fa, 0x3d4233dd, 0x2a1462b3];
+
+    values.iter().fold(1, |chk, value| {
+        let top = chk >> 25;
+        let chk = ((chk & 0x1ffffff) << 5) ^ (*value as u32);
+
+        (0..5).filter(|i| (top >> i) & 1 == 1).fold(chk, |chk, i| chk ^ GENERATOR[i])
+    })
+}
+
+fn hrp_expand(hrp: &str) -> Vec<u8> {
+    let mut values: Vec<u8> = hrp.bytes().map(|c| c >> 5).collect();
+    values.push(0);
+    values.extend(hrp.bytes().map(|c| c & 31));
+    values
+}
+
+/// Regroups bits, e.g. 5 bits values into bytes. Fails if the padding isn't made of fewer than
+/// `from` zero bits.
+fn convert_bits(values: &[u8], from: u32, to: u32) -> Option<Vec<u8>> {
+    let mut acc: u32 = 0;
+    let mut bits = 0;
+    let mut result = vec![];
+
+    for value in values {
+        acc = (acc << from) | (*value as u32);
+        bits += from;
+
+        while bits >= to {
+            bits -= to;
+            result.push(((acc >> bits) & ((1 << to) - 1)) as u8);
+        };
+    };
+
+    if bits >= from || (acc << (to - bits)) & ((1 << to) - 1) != 0 {
+        return None
+    };
+
+    Some(result)
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Base58 => write!(f, "invalid base58 address"),
+            Error::BadChecksum => write!(f, "invalid address checksum"),
+            Error::UnknownVersion(version) => write!(f, "unknown address version {:#04x}", version),
+            Error::WrongHrp(hrp) => write!(f, "address for another network: {}", hrp),
+            Error::Bech32(reason) => write!(f, "invalid bech32 address: {}", reason),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::keys;
+
+    fn script_hex(address: &str, params: &ChainParams) -> Result<String, Error> {
+        to_script(address, params).map(|script| script.to_hex())
+    }
+
+    #[test]
+    fn base58_to_script_test() {
+        let mainnet = ChainParams::mainnet();
+        let testnet = ChainParams::testnet();
+
+        // the address of the genesis block coinbase.
+        assert_eq!(
+            Ok(String::from("76a91462e907b15cbf27d5425399ebf6f0fb50ebb88f1888ac")),
+            script_hex("1A1zP1eP5QGefi2DMPTfTL5SLmv7DivfNa", &mainnet)
+        );
+        assert_eq!(
+            Ok(String::from("a914b472a266d0bd89c13706a4132ccfb16f7c3b9fcb87")),
+            script_hex("3J98t1WpEZ73CNmQviecrnyiWrnqRhWNLy", &mainnet)
+        );
+        assert_eq!(
+            Ok(String::from("76a914243f1394f44554f4ce3fd68649c19adc483ce92488ac")),
+            script_hex("mipcBbFg9gMiCh81Kj8tqqdgoZub1ZJRfn", &testnet)
+        );
+        assert_eq!(
+            Ok(String::from("a9144e9f39ca4688ff102128ea4ccda34105324305b087")),
+            script_hex("2MzQwSSnBHWHqSAqtTVQ6v47XtaisrJa1Vc", &testnet)
+        );
+    }
+
+    #[test]
+    fn base58_errors_test() {
+        let mainnet = ChainParams::mainnet();
+
+        assert_eq!(Err(Error::UnknownVersion(0x6f)), script_hex("mipcBbFg9gMiCh81Kj8tqqdgoZub1ZJRfn", &mainnet));
+        assert_eq!(Err(Error::BadChecksum), script_hex("1A1zP1eP5QGefi2DMPTfTL5SLmv7DivfNb", &mainnet));
+        assert_eq!(Err(Error::Base58), script_hex("1A1zP1eP5QGefi2DMPTfTL5SLmv7Divf0a", &mainnet));
+        assert_eq!(Err(Error::Base58), script_hex("todo", &mainnet));
+    }
+
+    #[test]
+    fn from_keychain_roundtrip_test() {
+        let keychain = keys::generate_keychain();
+        let params = ChainParams::regtest();
+        let address = from_keychain(&keychain, &params);
+
+        assert_eq!(Ok(Script::new_p2pkh(&hash160(&keychain.public_key_bytes()))), to_script(&address, &params));
+    }
+
+    // test vectors from BIP173 and BIP350
+    #[test]
+    fn bech32_to_script_test() {
+        let mainnet = ChainParams::mainnet();
+        let testnet = ChainParams::testnet();
+
+        assert_eq!(
+            Ok(String::from("0014751e76e8199196d454941c45d1b3a323f1433bd6")),
+            script_hex("BC1QW508D6QEJXTDG4Y5R3ZARVARY0C5XW7KV8F3T4", &mainnet)
+        );
+        assert_eq!(
+            Ok(String::from("00201863143c14c5166804bd19203356da136c985678cd4d27a1b8c6329604903262")),
+            script_hex("tb1qrp33g0q5c5txsp9arysrx4k6zdkfs4nce4xj0gdcccefvpysxf3q0sl5k7", &testnet)
+        );
+        assert_eq!(
+            Ok(String::from("6002751e")),
+            script_hex("BC1SW50QGDZ25J", &mainnet)
+        );
+        assert_eq!(
+            Ok(String::from("512079be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798")),
+            script_hex("bc1p0xlxvlhemja6c4dqv22uapctqupfhlxm9h8z3k2e72q4k9hcz7vqzk5jj0", &mainnet)
+        );
+        assert_eq!(
+            Ok(String::from("0014751e76e8199196d454941c45d1b3a323f1433bd6")),
+            script_hex("bcrt1qw508d6qejxtdg4y5r3zarvary0c5xw7kygt080", &ChainParams::regtest())
+        );
+    }
+
+    #[test]
+    fn bech32_errors_test() {
+        let mainnet = ChainParams::mainnet();
+
+        // segwit v0 with a bech32m checksum, and v1 with a bech32 checksum.
+        assert_eq!(Err(Error::BadChecksum), script_hex("bc1qw508d6qejxtdg4y5r3zarvary0c5xw7kemeawh", &mainnet));
+        assert_eq!(Err(Error::BadChecksum), script_hex("bc1p0xlxvlhemja6c4dqv22uapctqupfhlxm9h8z3k2e72q4k9hcz7vqh2y7hd", &mainnet));
+
+        assert_eq!(Err(Error::Bech32("mixed case")), script_hex("bc1QW508D6QEJXTDG4Y5R3ZARVARY0C5XW7KV8F3T4", &mainnet));
+        assert_eq!(Err(Error::Bech32("invalid character")), script_hex("bc1qw508d6qejxtdg4y5r3zarvary0c5xw7kv8f3tb", &mainnet));
+        assert_eq!(Err(Error::Bech32("invalid witness program length")), script_hex("bc1pw5dgrnzv", &mainnet));
+        assert_eq!(Err(Error::Bech32("invalid witness program length")), script_hex("BC1QR508D6QEJXTDG4Y5R3ZARVARYV98GJ9P", &mainnet));
+        assert_eq!(
+            Err(Error::Bech32("invalid padding")),
+            script_hex("tb1p0xlxvlhemja6c4dqv22uapctqupfhlxm9h8z3k2e72q4k9hcz7vpggkg4j", &ChainParams::testnet())
+        );
+
+        // a testnet address on mainnet.
+        assert_eq!(
+            Err(Error::WrongHrp(String::from("tb"))),
+            script_hex("tb1qrp33g0q5c5txsp9arysrx4k6zdkfs4nce4xj0gdcccefvpysxf3q0sl5k7", &mainnet)
+        );
+    }
+}
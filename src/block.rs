@@ -1,12 +1,24 @@
+use std::io::{Read, Write};
+
+use openssl::sha::{self, Sha256};
+
+use crate::encoding::{self, CompactSize, Decodable, Encodable, Error};
 use crate::hash::hash256;
-use crate::transactions::Transaction;
+use crate::merkleroot;
+use crate::script::Script;
+use crate::transactions::{Transaction, TxIn, TxOut};
 use crate::u256;
 
+// OP_RETURN, a push of 36 bytes, then the 0xaa21a9ed commitment header.
+const WITNESS_COMMITMENT_PREFIX: [u8; 6] = [0x6a, 0x24, 0xaa, 0x21, 0xa9, 0xed];
+
+#[derive(Clone, Debug, PartialEq, Eq)]
 pub struct Block {
     pub header: Header,
     pub transactions: Vec<Transaction>,
 }
 
+#[derive(Clone, Debug, PartialEq, Eq)]
 pub struct Header {
     pub version: u32,
     pub prev_block_hash: u256,
@@ -16,32 +28,164 @@ pub struct Header {
     pub nounce: u32,
 }
 
+/// The genesis block of mainnet.
 pub fn genesis() -> Block {
+    create_genesis(1231006505, 2083236893, 486604799)
+}
+
+/// A genesis block with the mainnet genesis coinbase. Every network starts from one, with its own
+/// time, nounce and bits.
+pub fn create_genesis(time: u32, nounce: u32, bits: u32) -> Block {
+    // The scriptSig embeds "The Times 03/Jan/2009 Chancellor on brink of second bailout for banks".
+    let coinbase = Transaction {
+        version: 1,
+        inputs: vec![
+            TxIn {
+                txid: u256::new(0),
+                vout: 0xffffffff,
+                script_sig: Script::from_hex(
+                    "04ffff001d0104455468652054696d65732030332f4a616e2f32303039204368616e63656c6c6f72\
+                     206f6e206272696e6b206f66207365636f6e64206261696c6f757420666f722062616e6b73"
+                ).unwrap(),
+                sequence: 0xffffffff,
+                witness: vec![],
+            }
+        ],
+        outputs: vec![
+            TxOut {
+                value: 50 * 100_000_000,
+                script_pub_key: Script::from_hex(
+                    "4104678afdb0fe5548271967f1a67130b7105cd6a828e03909a67962e0ea1f61deb649f6bc3f4cef\
+                     38c4f35504e51ec112de5c384df7ba0b8d578a4c702b6bf11d5fac"
+                ).unwrap(),
+            }
+        ],
+        lock_time: 0,
+    };
+
     Block {
         header: Header{
             version: 0x1,
             prev_block_hash: u256::new(0),
             merkle_root: u256::from_str_radix("4a5e1e4baab89f3a32518a88c31bc87f618f76673e2cc77ab2127b7afdeda33b", 16).unwrap(),
-            time: 1231006505,
-            bits: 486604799,
-            nounce: 2083236893,
+            time,
+            bits,
+            nounce,
         },
-        transactions: vec![],
+        transactions: vec![coinbase],
+    }
+}
+
+impl Header {
+    pub fn from_bytes(bytes: &[u8]) -> Result<Header, Error> {
+        encoding::deserialize(bytes)
+    }
+
+    pub fn from_hex(header_hex: &str) -> Result<Header, Error> {
+        encoding::deserialize_hex(header_hex)
+    }
+
+    pub fn hash(&self) -> u256 {
+        u256::from_le_bytes(hash256(&self.to_bytes()).try_into().unwrap())
+    }
+
+    /// The 80 bytes header, as it is hashed and sent on the wire.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        encoding::serialize(self)
+    }
+
+    pub fn to_hex(&self) -> String {
+        hex::encode(self.to_bytes())
+    }
+
+    /// Hashes this header with any nounce, for mining.
+    pub fn hasher(&self) -> HeaderHasher {
+        let bytes = self.to_bytes();
+        let mut midstate = Sha256::new();
+        midstate.update(&bytes[..64]);
+
+        HeaderHasher { midstate, tail: bytes[64..].try_into().unwrap() }
+    }
+}
+
+/// The SHA-256 state after the first 64 bytes of a header (one SHA-256 chunk), which don't depend
+/// on the nounce. Only the last 16 bytes are hashed again for each nounce: the end of the merkle
+/// root, the time, the bits and the nounce.
+#[derive(Clone)]
+pub struct HeaderHasher {
+    midstate: Sha256,
+    tail: [u8; 16],
+}
+
+impl HeaderHasher {
+    /// Same as `Header::hash` with the given nounce.
+    pub fn hash(&self, nounce: u32) -> u256 {
+        let mut tail = self.tail;
+        tail[12..].copy_from_slice(&nounce.to_le_bytes());
+
+        let mut hasher = self.midstate.clone();
+        hasher.update(&tail);
+
+        u256::from_le_bytes(sha::sha256(&hasher.finish()))
+    }
+}
+
+impl Encodable for Header {
+    fn consensus_encode<W: Write + ?Sized>(&self, writer: &mut W) -> Result<usize, Error> {
+        let mut size = self.version.consensus_encode(writer)?;
+        size += self.prev_block_hash.consensus_encode(writer)?;
+        size += self.merkle_root.consensus_encode(writer)?;
+        size += self.time.consensus_encode(writer)?;
+        size += self.bits.consensus_encode(writer)?;
+        size += self.nounce.consensus_encode(writer)?;
+        Ok(size)
+    }
+}
+
+impl Decodable for Header {
+    fn consensus_decode<R: Read + ?Sized>(reader: &mut R) -> Result<Self, Error> {
+        Ok(Header {
+            version: u32::consensus_decode(reader)?,
+            prev_block_hash: u256::consensus_decode(reader)?,
+            merkle_root: u256::consensus_decode(reader)?,
+            time: u32::consensus_decode(reader)?,
+            bits: u32::consensus_decode(reader)?,
+            nounce: u32::consensus_decode(reader)?,
+        })
     }
 }
 
 impl Block {
+    pub fn from_bytes(bytes: &[u8]) -> Result<Block, Error> {
+        encoding::deserialize(bytes)
+    }
+
+    pub fn from_hex(block_hex: &str) -> Result<Block, Error> {
+        encoding::deserialize_hex(block_hex)
+    }
+
     pub fn hash(&self) -> u256 {
-        let mut payload: Vec<u8> = Vec::new();
+        self.header.hash()
+    }
 
-        payload.extend(self.header.version.to_le_bytes());
-        payload.extend(self.header.prev_block_hash.to_le_bytes());
-        payload.extend(self.header.merkle_root.to_le_bytes());
-        payload.extend(self.header.time.to_le_bytes());
-        payload.extend(self.header.bits.to_le_bytes());
-        payload.extend(self.header.nounce.to_le_bytes());
+    /// The block serialized as header, transaction count, then every transaction with their
+    /// witness data.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        encoding::serialize(self)
+    }
 
-        u256::from_le_bytes(hash256(&payload).try_into().unwrap())
+    pub fn to_hex(&self) -> String {
+        hex::encode(self.to_bytes())
+    }
+
+    pub fn size(&self) -> usize {
+        self.to_bytes().len()
+    }
+
+    pub fn weight(&self) -> usize {
+        let header_and_count = 80 + CompactSize(self.transactions.len() as u64).size();
+
+        header_and_count * 4 + self.transactions.iter().map(|tx| tx.weight()).sum::<usize>()
     }
 
     pub fn hash_hex(&self) -> String {
@@ -51,6 +195,67 @@ impl Block {
     pub fn prev_block_hash_hex(&self) -> String {
         hex::encode(self.header.prev_block_hash.to_be_bytes())
     }
+
+    /// The witness commitment of the block, found in the last coinbase output whose scriptPubKey
+    /// starts with OP_RETURN 0xaa21a9ed.
+    pub fn witness_commitment(&self) -> Option<Vec<u8>> {
+        let coinbase = self.transactions.first()?;
+
+        coinbase.outputs
+            .iter()
+            .rev()
+            .map(|txout| txout.script_pub_key.as_bytes())
+            .find(|script| script.len() >= 38 && script.starts_with(&WITNESS_COMMITMENT_PREFIX))
+            .map(|script| script[6..38].to_vec())
+    }
+
+    // https://github.com/bitcoin/bips/blob/master/bip-0141.mediawiki#commitment-structure
+    //
+    // The commitment is hash256(witness root || witness reserved value), where the reserved value
+    // is the single 32 bytes item of the coinbase input witness. A block without a commitment
+    // can't have transactions with witness data.
+    pub fn check_witness_commitment(&self) -> bool {
+        let commitment = match self.witness_commitment() {
+            Some(commitment) => commitment,
+            None => return !self.transactions.iter().any(|tx| tx.has_witness()),
+        };
+
+        let reserved_value = match &self.transactions[0].inputs[..] {
+            [txin] if txin.witness.len() == 1 && txin.witness[0].len() == 32 => &txin.witness[0],
+            _ => return false,
+        };
+
+        let script = witness_commitment_script(&self.transactions, reserved_value);
+        script.as_bytes()[WITNESS_COMMITMENT_PREFIX.len()..] == commitment
+    }
+}
+
+/// The coinbase output script committing to the witnesses of `transactions`, with the given
+/// witness reserved value. The wtxid of the coinbase, the first transaction, doesn't matter.
+pub fn witness_commitment_script(transactions: &[Transaction], reserved_value: &[u8]) -> Script {
+    let mut payload = hex::decode(merkleroot::witness_root_from_transactions(transactions)).unwrap();
+    payload.extend(reserved_value);
+
+    let mut script = WITNESS_COMMITMENT_PREFIX.to_vec();
+    script.extend(hash256(&payload));
+    Script::from(script)
+}
+
+impl Encodable for Block {
+    fn consensus_encode<W: Write + ?Sized>(&self, writer: &mut W) -> Result<usize, Error> {
+        let mut size = self.header.consensus_encode(writer)?;
+        size += self.transactions.consensus_encode(writer)?;
+        Ok(size)
+    }
+}
+
+impl Decodable for Block {
+    fn consensus_decode<R: Read + ?Sized>(reader: &mut R) -> Result<Self, Error> {
+        Ok(Block {
+            header: Header::consensus_decode(reader)?,
+            transactions: Vec::<Transaction>::consensus_decode(reader)?,
+        })
+    }
 }
 
 #[cfg(test)]
@@ -72,6 +277,18 @@ mod test {
         assert_eq!(2083236893, header.nounce);
     }
 
+    #[test]
+    fn header_hasher_test() {
+        let header = genesis().header;
+        let hasher = header.hasher();
+
+        assert_eq!(genesis().hash(), hasher.hash(header.nounce));
+
+        for nounce in [0, 1, 0xdeadbeef, u32::MAX] {
+            assert_eq!(Header { nounce, ..header.clone() }.hash(), hasher.hash(nounce));
+        };
+    }
+
     #[test]
     fn genesis_hash_test() {
         assert_eq!(
@@ -88,6 +305,125 @@ mod test {
         );
     }
 
+    #[test]
+    fn genesis_merkle_root_test() {
+        let block = genesis();
+
+        assert_eq!(
+            hex::encode(block.header.merkle_root.to_le_bytes()),
+            merkleroot::from_transactions(&block.transactions)
+        );
+    }
+
+    #[test]
+    fn genesis_to_hex_test() {
+        let expected_hex = "0100000000000000000000000000000000000000000000000000000000000000000000003ba3ed\
+                            fd7a7b12b27ac72c3e67768f617fc81bc3888a51323a9fb8aa4b1e5e4a29ab5f49ffff001d1dac\
+                            2b7c0101000000010000000000000000000000000000000000000000000000000000000000000000\
+                            ffffffff4d04ffff001d0104455468652054696d65732030332f4a616e2f32303039204368616e\
+                            63656c6c6f72206f6e206272696e6b206f66207365636f6e64206261696c6f757420666f722062\
+                            616e6b73ffffffff0100f2052a01000000434104678afdb0fe5548271967f1a67130b7105cd6a8\
+                            28e03909a67962e0ea1f61deb649f6bc3f4cef38c4f35504e51ec112de5c384df7ba0b8d578a4c\
+                            702b6bf11d5fac00000000";
+
+        assert_eq!(expected_hex, genesis().to_hex());
+        assert_eq!(&expected_hex[..160], genesis().header.to_hex());
+        assert_eq!(Ok(genesis()), Block::from_hex(expected_hex));
+    }
+
+    #[test]
+    fn consensus_encode_size_test() {
+        let block = genesis();
+        let mut payload: Vec<u8> = Vec::new();
+
+        assert_eq!(Ok(80), block.header.consensus_encode(&mut payload));
+        assert_eq!(Ok(285), block.consensus_encode(&mut payload));
+        assert_eq!(80 + 285, payload.len());
+    }
+
+    #[test]
+    fn header_from_hex_test() {
+        let header_hex = genesis().header.to_hex();
+
+        assert_eq!(Ok(genesis().header), Header::from_hex(&header_hex));
+        assert_eq!(Err(Error::UnexpectedEnd), Header::from_hex(&header_hex[..158]));
+        assert_eq!(Err(Error::TrailingBytes(1)), Header::from_hex(&format!("{}00", header_hex)));
+    }
+
+    #[test]
+    fn mainnet_block_from_bytes_test() {
+        let bytes = include_bytes!("../test_data/mainnet_block_000000000000000000000c835b2adcaedc20fdf6ee440009c249452c726dafae.raw");
+        let block = Block::from_bytes(bytes).unwrap();
+
+        assert_eq!("000000000000000000000c835b2adcaedc20fdf6ee440009c249452c726dafae", block.hash_hex());
+        assert_eq!(0x3fffe004, block.header.version);
+        assert_eq!(0x170ed0eb, block.header.bits);
+        assert_eq!(2500, block.transactions.len());
+        assert_eq!(
+            hex::encode(block.header.merkle_root.to_le_bytes()),
+            merkleroot::from_transactions(&block.transactions)
+        );
+        assert!(block.check_witness_commitment());
+
+        assert_eq!(bytes.len(), block.size());
+        assert_eq!(bytes.to_vec(), block.to_bytes());
+    }
+
+    #[test]
+    fn block_from_bytes_truncated_test() {
+        let bytes = include_bytes!("../test_data/testnet_block_000000000000045e0b1660b6445b5e5c5ab63c9a4f956be7e1e69be04fa4497b.raw");
+
+        assert_eq!(Err(Error::UnexpectedEnd), Block::from_bytes(&bytes[..bytes.len() - 1]));
+        assert_eq!(Err(Error::UnexpectedEnd), Block::from_bytes(&bytes[..81]));
+    }
+
+    #[test]
+    fn segwit_block_test() {
+        let block = testnet_segwit_block();
+
+        assert_eq!(0x20000000, block.header.version);
+        assert_eq!(1472004949, block.header.time);
+        assert_eq!(0x1a06d450, block.header.bits);
+        assert_eq!(1879759182, block.header.nounce);
+        assert_eq!(4319, block.size());
+        assert_eq!(17168, block.weight());
+    }
+
+    #[test]
+    fn witness_commitment_test() {
+        let block = testnet_segwit_block();
+
+        // the merkle root commits to the txids, which don't cover the witness data.
+        assert_eq!(
+            hex::encode(block.header.merkle_root.to_le_bytes()),
+            merkleroot::from_transactions(&block.transactions)
+        );
+        assert_eq!(
+            "f91c46b49eb8a29089980f02ee6b57e7d63d33b18b4fddac2bcd7db2a3983704",
+            hex::encode(block.witness_commitment().unwrap())
+        );
+        assert!(block.check_witness_commitment());
+    }
+
+    #[test]
+    fn check_witness_commitment_invalid_test() {
+        let mut block = testnet_segwit_block();
+        block.transactions[1].inputs[0].witness = vec![vec![0x01]];
+        assert!(!block.check_witness_commitment());
+
+        let mut block = testnet_segwit_block();
+        block.transactions[0].inputs[0].witness = vec![];
+        assert!(!block.check_witness_commitment());
+
+        // no commitment, but a transaction has witness data.
+        let mut block = testnet_segwit_block();
+        block.transactions[0].outputs.pop();
+        assert!(!block.check_witness_commitment());
+
+        block.transactions[0].inputs[0].witness = vec![];
+        assert!(block.check_witness_commitment());
+    }
+
     #[test]
     fn prev_block_hash_hex_test() {
         assert_eq!(
@@ -95,4 +431,11 @@ mod test {
             genesis().prev_block_hash_hex()
         );
     }
+
+    fn testnet_segwit_block() -> Block {
+        // testnet block 000000000000045e0b1660b6445b5e5c5ab63c9a4f956be7e1e69be04fa4497b, its
+        // coinbase transaction commits to the witness root.
+        let bytes = include_bytes!("../test_data/testnet_block_000000000000045e0b1660b6445b5e5c5ab63c9a4f956be7e1e69be04fa4497b.raw");
+        Block::from_bytes(bytes).unwrap()
+    }
 }
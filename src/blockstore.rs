@@ -0,0 +1,470 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+use crate::block::Block;
+use crate::encoding::{self, Decodable, Encodable};
+use crate::u256;
+
+// Blocks are appended to blk00000.dat, blk00001.dat, ... in the `blocks` directory of the data
+// directory. Like bitcoin core, every block is framed by the network magic and its size:
+//
+//  magic (4 bytes) | size (u32, le) | serialized block
+//
+// index.dat maps every block hash to the file and offset of its serialized block, so blocks can
+// be read back without scanning the files.
+
+/// Size after which a new block file is started.
+pub const MAX_BLOCKFILE_SIZE: u64 = 0x8000000; // 128 MiB
+
+const INDEX_FILE: &str = "index.dat";
+
+/// Where a block is stored: the number of its blk file, and the offset of the serialized block
+/// (after the magic and size) in that file.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct BlockLocation {
+    pub file: u32,
+    pub offset: u32,
+    pub size: u32,
+}
+
+pub struct BlockStore {
+    dir: PathBuf,
+    magic: [u8; 4],
+    max_file_size: u64,
+    index: HashMap<u256, BlockLocation>,
+    current_file: u32,
+    current_file_size: u64,
+}
+
+#[derive(Debug)]
+pub enum Error {
+    Io(io::Error),
+    Encoding(encoding::Error),
+    /// The bytes before a block aren't the network magic.
+    BadMagic { file: u32, offset: u32 },
+}
+
+/// The default data directory: ~/.rcoin
+pub fn default_data_dir() -> PathBuf {
+    let home = std::env::var_os("HOME").unwrap_or_else(|| ".".into());
+    Path::new(&home).join(".rcoin")
+}
+
+impl BlockStore {
+    /// Opens the block store in `data_dir`, creating it if needed. Blocks are framed with the
+    /// `magic` of the network.
+    pub fn open(data_dir: &Path, magic: [u8; 4]) -> Result<BlockStore, Error> {
+        BlockStore::open_with_max_file_size(data_dir, magic, MAX_BLOCKFILE_SIZE)
+    }
+
+    pub fn open_with_max_file_size(data_dir: &Path, magic: [u8; 4], max_file_size: u64) -> Result<BlockStore, Error> {
+        let dir = data_dir.join("blocks");
+        fs::create_dir_all(&dir)?;
+
+        let mut current_file = 0;
+        while dir.join(block_file_name(current_file + 1)).exists() {
+            current_file += 1;
+        };
+
+        let current_file_size = match fs::metadata(dir.join(block_file_name(current_file))) {
+            Ok(metadata) => metadata.len(),
+            Err(_) => 0,
+        };
+
+        let mut store = BlockStore {
+            dir,
+            magic,
+            max_file_size,
+            index: HashMap::new(),
+            current_file,
+            current_file_size,
+        };
+
+        store.load_index()?;
+        Ok(store)
+    }
+
+    pub fn len(&self) -> usize {
+        self.index.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.index.is_empty()
+    }
+
+    pub fn contains(&self, hash: &u256) -> bool {
+        self.index.contains_key(hash)
+    }
+
+    pub fn location(&self, hash: &u256) -> Option<BlockLocation> {
+        self.index.get(hash).copied()
+    }
+
+    /// Appends the block to the current blk file, starting a new one if it would grow past the
+    /// maximum file size. Blocks that are already stored aren't written twice.
+    pub fn write_block(&mut self, block: &Block) -> Result<BlockLocation, Error> {
+        let hash = block.hash();
+        if let Some(location) = self.location(&hash) {
+            return Ok(location)
+        };
+
+        let payload = block.to_bytes();
+        let framed_size = 8 + payload.len() as u64;
+
+        if self.current_file_size > 0 && self.current_file_size + framed_size > self.max_file_size {
+            self.current_file += 1;
+            self.current_file_size = 0;
+        };
+
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(self.dir.join(block_file_name(self.current_file)))?;
+
+        file.write_all(&self.magic)?;
+        file.write_all(&(payload.len() as u32).to_le_bytes())?;
+        file.write_all(&payload)?;
+
+        let location = BlockLocation {
+            file: self.current_file,
+            offset: (self.current_file_size + 8) as u32,
+            size: payload.len() as u32,
+        };
+        self.current_file_size += framed_size;
+
+        self.append_to_index(&hash, &location)?;
+        self.index.insert(hash, location);
+
+        Ok(location)
+    }
+
+    pub fn read_block(&self, hash: &u256) -> Result<Option<Block>, Error> {
+        match self.location(hash) {
+            Some(location) => Ok(Some(self.read_block_at(&location)?)),
+            None => Ok(None),
+        }
+    }
+
+    pub fn read_block_at(&self, location: &BlockLocation) -> Result<Block, Error> {
+        let mut file = File::open(self.dir.join(block_file_name(location.file)))?;
+        file.seek(SeekFrom::Start(location.offset as u64 - 8))?;
+
+        let mut frame = [0; 8];
+        file.read_exact(&mut frame)?;
+
+        if frame[..4] != self.magic || frame[4..] != location.size.to_le_bytes() {
+            return Err(Error::BadMagic { file: location.file, offset: location.offset })
+        };
+
+        let mut payload = vec![0; location.size as usize];
+        file.read_exact(&mut payload)?;
+
+        Ok(Block::from_bytes(&payload)?)
+    }
+
+    /// Every stored block location, in the order the blocks were written.
+    pub fn locations(&self) -> Vec<(u256, BlockLocation)> {
+        let mut locations: Vec<_> = self.index.iter().map(|(hash, location)| (*hash, *location)).collect();
+        locations.sort_by_key(|(_, location)| (location.file, location.offset));
+        locations
+    }
+
+    /// Rebuilds index.dat by scanning the blk files. A file is read until its first frame that
+    /// doesn't start with the network magic, or that doesn't hold a valid block.
+    pub fn reindex(&mut self) -> Result<(), Error> {
+        self.index.clear();
+        fs::write(self.dir.join(INDEX_FILE), [])?;
+
+        for file in 0..=self.current_file {
+            let bytes = match fs::read(self.dir.join(block_file_name(file))) {
+                Ok(bytes) => bytes,
+                Err(error) if error.kind() == io::ErrorKind::NotFound => continue,
+                Err(error) => return Err(error.into()),
+            };
+
+            let mut offset = 0;
+            while offset + 8 <= bytes.len() && bytes[offset..(offset + 4)] == self.magic {
+                let size = u32::from_le_bytes(bytes[(offset + 4)..(offset + 8)].try_into().unwrap());
+                let payload = match bytes.get((offset + 8)..(offset + 8 + size as usize)) {
+                    Some(payload) => payload,
+                    None => break,
+                };
+
+                let block = match Block::from_bytes(payload) {
+                    Ok(block) => block,
+                    Err(_) => break,
+                };
+
+                let hash = block.hash();
+                if !self.contains(&hash) {
+                    let location = BlockLocation { file, offset: (offset + 8) as u32, size };
+                    self.append_to_index(&hash, &location)?;
+                    self.index.insert(hash, location);
+                };
+
+                offset += 8 + size as usize;
+            };
+        };
+
+        Ok(())
+    }
+
+    fn load_index(&mut self) -> Result<(), Error> {
+        let bytes = match fs::read(self.dir.join(INDEX_FILE)) {
+            Ok(bytes) => bytes,
+            Err(error) if error.kind() == io::ErrorKind::NotFound => return Ok(()),
+            Err(error) => return Err(error.into()),
+        };
+
+        // a record cut short by a crash is ignored, its block will be written again.
+        let mut reader = &bytes[..];
+        while let Ok((hash, location)) = <(u256, BlockLocation)>::consensus_decode(&mut reader) {
+            self.index.insert(hash, location);
+        };
+
+        Ok(())
+    }
+
+    fn append_to_index(&self, hash: &u256, location: &BlockLocation) -> Result<(), Error> {
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(self.dir.join(INDEX_FILE))?;
+
+        file.write_all(&encoding::serialize(&(*hash, *location)))?;
+        Ok(())
+    }
+}
+
+fn block_file_name(file: u32) -> String {
+    format!("blk{:05}.dat", file)
+}
+
+impl Encodable for BlockLocation {
+    fn consensus_encode<W: Write + ?Sized>(&self, writer: &mut W) -> Result<usize, encoding::Error> {
+        let mut size = self.file.consensus_encode(writer)?;
+        size += self.offset.consensus_encode(writer)?;
+        size += self.size.consensus_encode(writer)?;
+        Ok(size)
+    }
+}
+
+impl Decodable for BlockLocation {
+    fn consensus_decode<R: Read + ?Sized>(reader: &mut R) -> Result<Self, encoding::Error> {
+        Ok(BlockLocation {
+            file: u32::consensus_decode(reader)?,
+            offset: u32::consensus_decode(reader)?,
+            size: u32::consensus_decode(reader)?,
+        })
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Io(error) => write!(f, "{}", error),
+            Error::Encoding(error) => write!(f, "invalid block data: {}", error),
+            Error::BadMagic { file, offset } => write!(f, "bad block framing in {} at offset {}", block_file_name(*file), offset),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<io::Error> for Error {
+    fn from(error: io::Error) -> Error {
+        Error::Io(error)
+    }
+}
+
+impl From<encoding::Error> for Error {
+    fn from(error: encoding::Error) -> Error {
+        Error::Encoding(error)
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod test {
+    use super::*;
+    use crate::block::genesis;
+
+    use std::ops::Deref;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    const MAGIC: [u8; 4] = [0xf9, 0xbe, 0xb4, 0xd9];
+
+    /// A test directory, removed with its files when dropped.
+    pub(crate) struct TempDir(PathBuf);
+
+    impl Deref for TempDir {
+        type Target = Path;
+
+        fn deref(&self) -> &Path {
+            &self.0
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    /// An empty directory under the system temp directory, unique to the calling test.
+    pub(crate) fn temp_data_dir(name: &str) -> TempDir {
+        static COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+        let dir = std::env::temp_dir().join(format!(
+            "rcoin-{}-{}-{}",
+            name,
+            std::process::id(),
+            COUNTER.fetch_add(1, Ordering::SeqCst)
+        ));
+
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        TempDir(dir)
+    }
+
+    fn testnet_block() -> Block {
+        let bytes = include_bytes!("../test_data/testnet_block_000000000000045e0b1660b6445b5e5c5ab63c9a4f956be7e1e69be04fa4497b.raw");
+        Block::from_bytes(bytes).unwrap()
+    }
+
+    #[test]
+    fn write_and_read_block_test() {
+        let data_dir = temp_data_dir("write-read");
+        let mut store = BlockStore::open(&data_dir, MAGIC).unwrap();
+
+        let location = store.write_block(&genesis()).unwrap();
+        assert_eq!(BlockLocation { file: 0, offset: 8, size: 285 }, location);
+
+        let location = store.write_block(&testnet_block()).unwrap();
+        assert_eq!(BlockLocation { file: 0, offset: 285 + 16, size: 4319 }, location);
+
+        assert_eq!(2, store.len());
+        assert_eq!(Some(genesis()), store.read_block(&genesis().hash()).unwrap());
+        assert_eq!(Some(testnet_block()), store.read_block(&testnet_block().hash()).unwrap());
+        assert_eq!(None, store.read_block(&u256::new(1)).unwrap());
+
+        // magic, size, then the block.
+        let file = fs::read(data_dir.join("blocks").join("blk00000.dat")).unwrap();
+        assert_eq!("f9beb4d91d010000", hex::encode(&file[..8]));
+        assert_eq!(genesis().to_bytes(), file[8..(8 + 285)].to_vec());
+    }
+
+    #[test]
+    fn write_block_twice_test() {
+        let data_dir = temp_data_dir("write-twice");
+        let mut store = BlockStore::open(&data_dir, MAGIC).unwrap();
+
+        let location = store.write_block(&genesis()).unwrap();
+        assert_eq!(location, store.write_block(&genesis()).unwrap());
+        assert_eq!(1, store.len());
+    }
+
+    #[test]
+    fn rotate_block_files_test() {
+        let data_dir = temp_data_dir("rotate");
+        let mut store = BlockStore::open_with_max_file_size(&data_dir, MAGIC, 1000).unwrap();
+
+        store.write_block(&genesis()).unwrap();
+        let location = store.write_block(&testnet_block()).unwrap();
+
+        assert_eq!(BlockLocation { file: 1, offset: 8, size: 4319 }, location);
+        assert!(data_dir.join("blocks").join("blk00001.dat").exists());
+        assert_eq!(Some(testnet_block()), store.read_block(&testnet_block().hash()).unwrap());
+    }
+
+    #[test]
+    fn reopen_store_test() {
+        let data_dir = temp_data_dir("reopen");
+
+        let mut store = BlockStore::open(&data_dir, MAGIC).unwrap();
+        store.write_block(&genesis()).unwrap();
+        store.write_block(&testnet_block()).unwrap();
+
+        let mut store = BlockStore::open(&data_dir, MAGIC).unwrap();
+        assert_eq!(2, store.len());
+        assert_eq!(Some(genesis()), store.read_block(&genesis().hash()).unwrap());
+
+        // appends to the last block file.
+        let mut block = genesis();
+        block.header.nounce += 1;
+        assert_eq!(BlockLocation { file: 0, offset: 293 + 4327 + 8, size: 285 }, store.write_block(&block).unwrap());
+    }
+
+    #[test]
+    fn truncated_index_test() {
+        let data_dir = temp_data_dir("truncated-index");
+
+        let mut store = BlockStore::open(&data_dir, MAGIC).unwrap();
+        store.write_block(&genesis()).unwrap();
+        store.write_block(&testnet_block()).unwrap();
+
+        let index_path = data_dir.join("blocks").join(INDEX_FILE);
+        let index = fs::read(&index_path).unwrap();
+        fs::write(&index_path, &index[..index.len() - 1]).unwrap();
+
+        let store = BlockStore::open(&data_dir, MAGIC).unwrap();
+        assert_eq!(1, store.len());
+        assert!(store.contains(&genesis().hash()));
+    }
+
+    #[test]
+    fn reindex_test() {
+        let data_dir = temp_data_dir("reindex");
+
+        let mut store = BlockStore::open_with_max_file_size(&data_dir, MAGIC, 1000).unwrap();
+        store.write_block(&genesis()).unwrap();
+        store.write_block(&testnet_block()).unwrap();
+        let locations = store.locations();
+
+        fs::remove_file(data_dir.join("blocks").join(INDEX_FILE)).unwrap();
+
+        let mut store = BlockStore::open_with_max_file_size(&data_dir, MAGIC, 1000).unwrap();
+        assert!(store.is_empty());
+
+        store.reindex().unwrap();
+        assert_eq!(locations, store.locations());
+
+        // the rebuilt index is written back.
+        let store = BlockStore::open_with_max_file_size(&data_dir, MAGIC, 1000).unwrap();
+        assert_eq!(locations, store.locations());
+    }
+
+    #[test]
+    fn reindex_partial_write_test() {
+        let data_dir = temp_data_dir("reindex-partial");
+
+        let mut store = BlockStore::open(&data_dir, MAGIC).unwrap();
+        store.write_block(&genesis()).unwrap();
+
+        // a block cut short by a crash.
+        let path = data_dir.join("blocks").join("blk00000.dat");
+        let mut file = fs::read(&path).unwrap();
+        file.extend(&file.clone()[..100]);
+        fs::write(&path, file).unwrap();
+
+        store.reindex().unwrap();
+        assert_eq!(vec![(genesis().hash(), BlockLocation { file: 0, offset: 8, size: 285 })], store.locations());
+    }
+
+    #[test]
+    fn bad_magic_test() {
+        let data_dir = temp_data_dir("bad-magic");
+
+        let mut store = BlockStore::open(&data_dir, MAGIC).unwrap();
+        let location = store.write_block(&genesis()).unwrap();
+
+        let path = data_dir.join("blocks").join("blk00000.dat");
+        let mut file = fs::read(&path).unwrap();
+        file[0] = 0x00;
+        fs::write(&path, file).unwrap();
+
+        assert!(matches!(store.read_block_at(&location), Err(Error::BadMagic { file: 0, offset: 8 })));
+    }
+}
@@ -0,0 +1,333 @@
+use std::cmp::Ordering;
+use std::collections::{BTreeSet, HashMap, HashSet};
+
+use crate::block;
+use crate::mempool::{Mempool, MempoolEntry};
+use crate::script::Script;
+use crate::transactions::Transaction;
+use crate::u256;
+use crate::validation::{BlockContext, MAX_BLOCK_WEIGHT};
+
+// Selects the mempool transactions of the next block, like bitcoin core's BlockAssembler.
+//
+// https://github.com/bitcoin/bitcoin/blob/master/src/node/miner.cpp
+//
+// Transactions are picked by ancestor fee rate: the fees of a transaction and of its ancestors
+// not in the block yet, over their weight. A child paying a high fee gets its low fee parents
+// mined along with it.
+
+/// Weight kept for the header, the transaction count and the coinbase.
+pub const RESERVED_WEIGHT: usize = 4000;
+
+/// The block version of the templates.
+pub const BLOCK_VERSION: u32 = 1;
+
+/// A block to mine, except for its coinbase and proof of work.
+pub struct BlockTemplate {
+    pub version: u32,
+    pub context: BlockContext,
+    /// The selected transactions, in block order. A transaction always comes after its parents.
+    pub entries: Vec<MempoolEntry>,
+    /// The subsidy and the fees of the transactions.
+    pub coinbase_value: u64,
+    /// The coinbase output committing to the witnesses, when a transaction has witness data. The
+    /// coinbase witness must then be the 32 zero bytes reserved value.
+    pub witness_commitment: Option<Script>,
+}
+
+/// A template with the mempool transactions paying the best fee rates, up to the weight limit.
+pub fn new(context: BlockContext, mempool: &Mempool) -> BlockTemplate {
+    let mut included: HashSet<u256> = HashSet::new();
+    let mut failed: HashSet<u256> = HashSet::new();
+    let mut entries: Vec<MempoolEntry> = vec![];
+    let mut weight = RESERVED_WEIGHT;
+
+    // the packages with all their ancestors, sorted once, the best last.
+    let packages: HashMap<u256, Package> = mempool.entries()
+        .map(|entry| (entry.txid, Package::new(entry, mempool)))
+        .collect();
+    let mut sorted: Vec<Package> = packages.values().copied().collect();
+    sorted.sort();
+
+    // like bitcoin core's modified entries: the packages of the transactions with ancestors in
+    // the block, without those ancestors. Only the descendants of an included transaction change.
+    let mut modified: HashMap<u256, Package> = HashMap::new();
+    let mut modified_sorted: BTreeSet<Package> = BTreeSet::new();
+
+    loop {
+        // a sorted package is outdated once its transaction is modified.
+        while let Some(package) = sorted.last() {
+            if !included.contains(&package.txid) && !failed.contains(&package.txid) && !modified.contains_key(&package.txid) {
+                break
+            };
+            sorted.pop();
+        };
+
+        let package = match (sorted.last(), modified_sorted.last()) {
+            (None, None) => break,
+            (Some(package), Some(modified_package)) if package > modified_package => sorted.pop().unwrap(),
+            (Some(_), None) => sorted.pop().unwrap(),
+            (_, Some(_)) => {
+                let package = modified_sorted.pop_last().unwrap();
+                modified.remove(&package.txid);
+                package
+            },
+        };
+
+        if weight + package.weight > MAX_BLOCK_WEIGHT {
+            failed.insert(package.txid);
+            continue
+        };
+
+        // a transaction has more ancestors than any of its ancestors, so they come first.
+        let mut txids: Vec<u256> = mempool.ancestors(&package.txid)
+            .into_iter()
+            .filter(|txid| !included.contains(txid))
+            .chain([package.txid])
+            .collect();
+        txids.sort_by_key(|txid| (mempool.ancestors(txid).len(), *txid));
+        included.extend(&txids);
+
+        for txid in txids {
+            let entry = mempool.get(&txid).unwrap();
+
+            for descendant in mempool.descendants(&txid) {
+                if included.contains(&descendant) || failed.contains(&descendant) {
+                    continue
+                };
+
+                let mut descendant_package = match modified.remove(&descendant) {
+                    Some(package) => {
+                        modified_sorted.remove(&package);
+                        package
+                    },
+                    None => packages[&descendant],
+                };
+                descendant_package.fee -= entry.modified_fee();
+                descendant_package.weight -= entry.weight;
+
+                modified.insert(descendant, descendant_package);
+                modified_sorted.insert(descendant_package);
+            };
+
+            entries.push(entry.clone());
+        };
+        weight += package.weight;
+    };
+
+    let fees: u64 = entries.iter().map(|entry| entry.fee).sum();
+    let witness_commitment = witness_commitment(&entries);
+
+    BlockTemplate {
+        version: BLOCK_VERSION,
+        coinbase_value: context.subsidy + fees,
+        context,
+        entries,
+        witness_commitment,
+    }
+}
+
+impl BlockTemplate {
+    pub fn transactions(&self) -> impl Iterator<Item = &Transaction> {
+        self.entries.iter().map(|entry| &entry.tx)
+    }
+
+    pub fn fees(&self) -> u64 {
+        self.entries.iter().map(|entry| entry.fee).sum()
+    }
+
+    /// Weight of the selected transactions, without the header and the coinbase.
+    pub fn weight(&self) -> usize {
+        self.entries.iter().map(|entry| entry.weight).sum()
+    }
+}
+
+/// A transaction and its ancestors that aren't in the block yet. Their fees are the modified
+/// fees, but the coinbase only claims the real fees. Packages are ordered by fee rate, then by
+/// txid, the lowest first: the best package is the greatest.
+#[derive(Clone, Copy, Debug)]
+struct Package {
+    txid: u256,
+    fee: u64,
+    weight: usize,
+}
+
+impl Package {
+    fn new(entry: &MempoolEntry, mempool: &Mempool) -> Package {
+        let (fee, weight) = mempool.ancestors(&entry.txid).iter()
+            .map(|txid| mempool.get(txid).unwrap())
+            .fold((entry.modified_fee(), entry.weight), |(fee, weight), entry| (fee + entry.modified_fee(), weight + entry.weight));
+
+        Package { txid: entry.txid, fee, weight }
+    }
+
+    /// Compares fee / weight without rounding.
+    fn compare_fee_rate(&self, other: &Package) -> Ordering {
+        (self.fee as u128 * other.weight as u128).cmp(&(other.fee as u128 * self.weight as u128))
+    }
+}
+
+impl Ord for Package {
+    fn cmp(&self, other: &Package) -> Ordering {
+        self.compare_fee_rate(other).then(other.txid.cmp(&self.txid))
+    }
+}
+
+impl PartialOrd for Package {
+    fn partial_cmp(&self, other: &Package) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for Package {
+    fn eq(&self, other: &Package) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Package {}
+
+// https://github.com/bitcoin/bips/blob/master/bip-0141.mediawiki#commitment-structure
+fn witness_commitment(entries: &[MempoolEntry]) -> Option<Script> {
+    if !entries.iter().any(|entry| entry.tx.has_witness()) {
+        return None
+    };
+
+    // the coinbase wtxid doesn't matter, it's replaced by zeros.
+    let mut transactions = vec![Transaction { version: 1, inputs: vec![], outputs: vec![], lock_time: 0 }];
+    transactions.extend(entries.iter().map(|entry| entry.tx.clone()));
+
+    Some(block::witness_commitment_script(&transactions, &[0; 32]))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::block::Block;
+    use crate::mempool::test::funded_utxos;
+    use crate::transactions::test::{outpoint, spend};
+    use crate::mempool;
+    use crate::miner;
+    use crate::params::ChainParams;
+    use crate::serializers::template_serializer;
+    use crate::utxo::UtxoSet;
+    use crate::validation::{self, COIN};
+
+    fn context(height: u32) -> BlockContext {
+        let genesis = ChainParams::regtest().genesis;
+
+        BlockContext {
+            height,
+            prev_block_hash: genesis.hash(),
+            bits: 0x207fffff,
+            subsidy: 50 * COIN,
+            median_time_past: genesis.header.time,
+            now: genesis.header.time + 600,
+            enforce_bip34: true,
+        }
+    }
+
+    fn mine(template: &BlockTemplate) -> Block {
+        let mut block = miner::new_with_threads(Script::from(vec![0x51]), b"", 1).next(template);
+        block.header.time = template.context.now;
+
+        crate::chain::test::regrind(block)
+    }
+
+    fn validate(template: &BlockTemplate, utxos: &UtxoSet) -> Result<(), validation::BlockError> {
+        validation::validate_block(&mine(template), &template.context, utxos)
+    }
+
+    #[test]
+    fn empty_mempool_test() {
+        let template = new(context(1), &mempool::new());
+
+        assert!(template.entries.is_empty());
+        assert_eq!(50 * COIN, template.coinbase_value);
+        assert_eq!(None, template.witness_commitment);
+        assert_eq!(Ok(()), validate(&template, &UtxoSet::default()));
+    }
+
+    #[test]
+    fn ancestor_fee_rate_test() {
+        let (utxos, funding, height) = funded_utxos(2);
+        let mut mempool = mempool::new();
+
+        // the child pays for its parent: together they pay more than the other transaction.
+        let parent = spend(&funding[..1], &[50 * COIN - 100]);
+        let child = spend(&[outpoint(&parent, 0)], &[50 * COIN - 100_000]);
+        let other = spend(&funding[1..], &[50 * COIN - 10_000]);
+
+        for tx in [&parent, &child, &other] {
+            mempool.add(tx.clone(), &utxos, height).unwrap();
+        };
+
+        let template = new(context(height), &mempool);
+        let txids: Vec<_> = template.entries.iter().map(|entry| entry.txid).collect();
+
+        assert_eq!(vec![parent.txid(), child.txid(), other.txid()], txids);
+        assert_eq!(110_000, template.fees());
+        assert_eq!(50 * COIN + 110_000, template.coinbase_value);
+        assert_eq!(Ok(()), validate(&template, &utxos));
+
+        // the getblocktemplate json refers to the parent of the child by its position.
+        let json: serde_json::Value = serde_json::from_str(&template_serializer::to_json(&template)).unwrap();
+        assert_eq!(serde_json::json!([1]), json["transactions"][1]["depends"]);
+        assert_eq!(serde_json::json!(50 * COIN + 110_000), json["coinbasevalue"]);
+    }
+
+    #[test]
+    fn modified_package_test() {
+        let (utxos, funding, height) = funded_utxos(2);
+        let mut mempool = mempool::new();
+
+        // once the parent is in the block, the child only pays for itself: less than the other.
+        let parent = spend(&funding[..1], &[50 * COIN - 100_000]);
+        let child = spend(&[outpoint(&parent, 0)], &[50 * COIN - 101_000]);
+        let other = spend(&funding[1..], &[50 * COIN - 5000]);
+
+        for tx in [&parent, &child, &other] {
+            mempool.add(tx.clone(), &utxos, height).unwrap();
+        };
+
+        let template = new(context(height), &mempool);
+        let txids: Vec<_> = template.entries.iter().map(|entry| entry.txid).collect();
+
+        assert_eq!(vec![parent.txid(), other.txid(), child.txid()], txids);
+        assert_eq!(Ok(()), validate(&template, &utxos));
+    }
+
+    #[test]
+    fn weight_limit_test() {
+        let (utxos, funding, height) = funded_utxos(11);
+        let mut mempool = mempool::new();
+
+        // transactions of about 397k weight, ten of them fit in a block.
+        for (i, outpoint) in funding.iter().enumerate() {
+            let tx = spend(&[*outpoint], &vec![COIN / 100 + i as u64; 3100]);
+            mempool.add(tx, &utxos, height).unwrap();
+        };
+
+        let template = new(context(height), &mempool);
+        assert_eq!(10, template.entries.len());
+        assert!(RESERVED_WEIGHT + template.weight() <= MAX_BLOCK_WEIGHT);
+
+        // the one left out pays the lowest fee rate.
+        let lowest = mempool.entries().max_by_key(|entry| entry.tx.outputs[0].value).unwrap();
+        assert!(!template.entries.contains(lowest));
+    }
+
+    #[test]
+    fn witness_commitment_test() {
+        let (utxos, funding, height) = funded_utxos(1);
+        let mut mempool = mempool::new();
+
+        let mut tx = spend(&funding, &[49 * COIN]);
+        tx.inputs[0].witness = vec![vec![0xab]];
+        mempool.add(tx, &utxos, height).unwrap();
+
+        let template = new(context(height), &mempool);
+        assert!(template.witness_commitment.is_some());
+        assert_eq!(Ok(()), validate(&template, &utxos));
+    }
+}
@@ -0,0 +1,755 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::path::Path;
+use std::time::SystemTime;
+
+use crate::block::{Block, Header};
+use crate::blockstore::{self, BlockStore};
+use crate::difficulty;
+use crate::params::ChainParams;
+use crate::u256;
+use crate::utxo::{self, BlockUndo, UtxoSet};
+use crate::validation::{self, BlockContext, BlockError};
+
+// Every stored block whose parent is known is kept in the block index, a tree rooted at genesis.
+// The active chain is the branch from genesis to the tip with the most cumulative work (the
+// chainwork). When another branch gets more work, the chain reorganizes: the active blocks after
+// the fork are disconnected from the UTXO set, and the blocks of the new branch are connected.
+//
+// Blocks are only fully validated once they are connected. A branch with a block that fails
+// validation is marked invalid, and the chain goes back to the best valid branch.
+//
+// On startup, the block index is rebuilt from the blocks in the block store, and the best chain
+// is connected from genesis to rebuild the UTXO set.
+
+/// The active chain: the hash of the block at every height, from genesis to the tip, and the
+/// outputs it leaves unspent.
+pub struct Chain {
+    params: ChainParams,
+    store: BlockStore,
+    index: HashMap<u256, IndexEntry>,
+    children: HashMap<u256, Vec<u256>>,
+    active: Vec<u256>,
+    tip: Block,
+    utxos: UtxoSet,
+    undo: Vec<BlockUndo>,
+}
+
+struct IndexEntry {
+    header: Header,
+    height: usize,
+    chainwork: u256,
+    /// Order in which the blocks were added. On equal chainwork, the first block wins.
+    sequence: usize,
+    status: Status,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Status {
+    /// Not known to be invalid: the block passed `check_block`, and maybe full validation.
+    Valid,
+    Invalid(BlockError),
+    InvalidParent,
+}
+
+#[derive(Debug)]
+pub enum Error {
+    Store(blockstore::Error),
+    InvalidBlock { hash: u256, error: BlockError },
+    /// The parent of the block isn't in the block index.
+    UnknownParent { hash: u256 },
+    Utxo(utxo::Error),
+}
+
+impl Chain {
+    /// Loads the chain of the network stored in `data_dir`, writing the genesis block if the
+    /// store is empty. With `reindex`, the block index is first rebuilt from the raw block files.
+    pub fn load(params: &ChainParams, data_dir: &Path, reindex: bool) -> Result<Chain, Error> {
+        let mut store = BlockStore::open(&data_dir.join(params.data_subdir), params.magic)?;
+        let genesis = params.genesis.clone();
+
+        if reindex {
+            store.reindex()?;
+        };
+
+        if !store.contains(&genesis.hash()) {
+            store.write_block(&genesis)?;
+        };
+
+        let mut stored_children: HashMap<u256, Vec<(u256, Header)>> = HashMap::new();
+        for (hash, location) in store.locations() {
+            let header = store.read_block_at(&location)?.header;
+            stored_children.entry(header.prev_block_hash).or_default().push((hash, header));
+        };
+
+        let mut chain = Chain {
+            params: params.clone(),
+            store,
+            index: HashMap::new(),
+            children: HashMap::new(),
+            active: vec![],
+            tip: genesis.clone(),
+            utxos: utxo::new(),
+            undo: vec![],
+        };
+
+        chain.insert_index_entry(genesis.hash(), genesis.header.clone(), None);
+        chain.active.push(genesis.hash());
+        chain.undo.push(BlockUndo::default());
+
+        // index the stored blocks from genesis, parents first.
+        let mut queue = vec![genesis.hash()];
+
+        while let Some(hash) = queue.pop() {
+            for (child, header) in stored_children.remove(&hash).into_iter().flatten() {
+                let block = chain.store.read_block(&child)?.unwrap();
+
+                // blocks that fail the checks, and the blocks built on top of them, are left out.
+                if validation::check_block(&block).is_err() {
+                    continue
+                };
+
+                chain.insert_index_entry(child, header, Some(hash));
+                queue.push(child);
+            };
+        };
+
+        // invalid blocks in the store were already reported when they were added.
+        match chain.activate_best_chain() {
+            Ok(()) | Err(Error::InvalidBlock { .. }) => Ok(chain),
+            Err(error) => Err(error),
+        }
+    }
+
+    pub fn params(&self) -> &ChainParams {
+        &self.params
+    }
+
+    pub fn tip(&self) -> &Block {
+        &self.tip
+    }
+
+    /// The height of the tip, genesis being at height 0.
+    pub fn height(&self) -> usize {
+        self.active.len() - 1
+    }
+
+    /// The cumulative work of the active chain.
+    pub fn chainwork(&self) -> u256 {
+        self.index[&self.tip.hash()].chainwork
+    }
+
+    /// The hash of the block at `height` in the active chain.
+    pub fn hash_at(&self, height: usize) -> Option<u256> {
+        self.active.get(height).copied()
+    }
+
+    /// Whether the block is in the block index, on the active chain or not.
+    pub fn contains(&self, hash: &u256) -> bool {
+        self.index.contains_key(hash)
+    }
+
+    pub fn utxos(&self) -> &UtxoSet {
+        &self.utxos
+    }
+
+    /// The median timestamp of the last blocks of the active chain.
+    pub fn median_time_past(&self) -> u32 {
+        let times: Vec<u32> = self.active.iter()
+            .rev()
+            .take(validation::MEDIAN_TIME_SPAN)
+            .rev()
+            .map(|hash| self.index[hash].header.time)
+            .collect();
+
+        validation::median_time_past(&times)
+    }
+
+    /// The bits required for the next block, timestamped `time`: the bits of the tip, except
+    /// every `RETARGET_INTERVAL` blocks where the difficulty is adjusted to the time it took to
+    /// mine the last interval.
+    ///
+    /// When the params allow min difficulty blocks, a block more than twice the target spacing
+    /// after the tip is mined at the easiest difficulty, and the next ones go back to the bits of
+    /// the last block that wasn't.
+    pub fn next_bits(&self, time: u32) -> u32 {
+        let height = self.active.len();
+        let tip = &self.tip.header;
+
+        if !height.is_multiple_of(difficulty::RETARGET_INTERVAL) {
+            if !self.params.pow_allow_min_difficulty_blocks {
+                return tip.bits
+            };
+
+            let limit_bits = difficulty::target_to_bits(self.params.pow_limit);
+            if time > tip.time.saturating_add(2 * difficulty::TARGET_SPACING) {
+                return limit_bits
+            };
+
+            let mut last = height - 1;
+            while !last.is_multiple_of(difficulty::RETARGET_INTERVAL) && self.header_at(last).bits == limit_bits {
+                last -= 1;
+            };
+
+            return self.header_at(last).bits
+        };
+
+        if self.params.pow_no_retargeting {
+            return tip.bits
+        };
+
+        let first = self.header_at(height - difficulty::RETARGET_INTERVAL);
+        difficulty::retarget(tip.bits, first.time, tip.time, self.params.pow_limit)
+    }
+
+    /// What the next block must build on: the tip, at the next height, with the bits required
+    /// for a block mined now.
+    pub fn next_block_context(&self) -> BlockContext {
+        let median_time_past = self.median_time_past();
+        let now = current_time();
+
+        BlockContext {
+            height: self.active.len() as u32,
+            prev_block_hash: self.tip.hash(),
+            bits: self.next_bits(now.max(median_time_past + 1)),
+            subsidy: validation::block_subsidy(self.active.len() as u32, &self.params),
+            median_time_past,
+            now,
+            enforce_bip34: self.active.len() as u32 >= self.params.bip34_height,
+        }
+    }
+
+    /// The header of the active block at `height`.
+    fn header_at(&self, height: usize) -> &Header {
+        &self.index[&self.active[height]].header
+    }
+
+    /// Checks and stores a block whose parent is known, then switches to the branch with the most
+    /// work. Fails if the block, or a block of its branch, turns out to be invalid.
+    pub fn add_block(&mut self, block: Block) -> Result<(), Error> {
+        let hash = block.hash();
+
+        if self.contains(&hash) {
+            return Ok(())
+        };
+
+        let parent = match self.index.get(&block.header.prev_block_hash) {
+            Some(parent) => parent,
+            None => return Err(Error::UnknownParent { hash }),
+        };
+
+        if let Status::Invalid(_) | Status::InvalidParent = parent.status {
+            return Err(Error::InvalidBlock { hash, error: BlockError::BadPrevBlock })
+        };
+
+        if let Err(error) = validation::check_block(&block) {
+            return Err(Error::InvalidBlock { hash, error })
+        };
+
+        self.store.write_block(&block)?;
+        self.insert_index_entry(hash, block.header.clone(), Some(block.header.prev_block_hash));
+
+        self.activate_best_chain()
+    }
+
+    fn insert_index_entry(&mut self, hash: u256, header: Header, parent: Option<u256>) {
+        let (height, chainwork) = match parent {
+            Some(parent) => {
+                self.children.entry(parent).or_default().push(hash);
+                (self.index[&parent].height + 1, self.index[&parent].chainwork)
+            },
+            None => (0, u256::new(0)),
+        };
+
+        let entry = IndexEntry {
+            chainwork: chainwork + difficulty::work_from_bits(header.bits),
+            header,
+            height,
+            sequence: self.index.len(),
+            status: Status::Valid,
+        };
+
+        self.index.insert(hash, entry);
+    }
+
+    /// The valid block with the most chainwork.
+    fn best_tip(&self) -> u256 {
+        let (hash, _) = self.index.iter()
+            .filter(|(_, entry)| entry.status == Status::Valid)
+            .max_by_key(|(_, entry)| (entry.chainwork, std::cmp::Reverse(entry.sequence)))
+            .unwrap();
+
+        *hash
+    }
+
+    /// Reorganizes the active chain until its tip is the best valid block. Returns the first
+    /// validation error found along the way, once the chain is on its best valid branch.
+    fn activate_best_chain(&mut self) -> Result<(), Error> {
+        let mut failure = None;
+
+        loop {
+            let best = self.best_tip();
+            if best == self.tip.hash() {
+                break
+            };
+
+            // the blocks of the new branch, down to the fork with the active chain.
+            let mut branch = vec![];
+            let mut hash = best;
+            while self.active.get(self.index[&hash].height) != Some(&hash) {
+                branch.push(hash);
+                hash = self.index[&hash].header.prev_block_hash;
+            };
+
+            while self.tip.hash() != hash {
+                self.disconnect_tip()?;
+            };
+
+            for hash in branch.into_iter().rev() {
+                let block = self.store.read_block(&hash)?.unwrap();
+
+                match self.connect(block) {
+                    Ok(()) => {},
+                    Err(Error::InvalidBlock { hash, error }) => {
+                        self.mark_invalid(hash, error.clone());
+                        failure.get_or_insert(Error::InvalidBlock { hash, error });
+                        break
+                    },
+                    Err(error) => return Err(error),
+                };
+            };
+        };
+
+        match failure {
+            Some(error) => Err(error),
+            None => Ok(()),
+        }
+    }
+
+    fn mark_invalid(&mut self, hash: u256, error: BlockError) {
+        self.index.get_mut(&hash).unwrap().status = Status::Invalid(error);
+
+        let mut descendants = self.children.get(&hash).cloned().unwrap_or_default();
+        while let Some(hash) = descendants.pop() {
+            self.index.get_mut(&hash).unwrap().status = Status::InvalidParent;
+            descendants.extend(self.children.get(&hash).into_iter().flatten().copied());
+        };
+    }
+
+    /// Removes the tip from the active chain, and restores the outputs it spent. The block stays
+    /// in the block store.
+    fn disconnect_tip(&mut self) -> Result<Block, Error> {
+        let undo = self.undo.pop().unwrap();
+        self.utxos.disconnect_block(&self.tip, &undo)?;
+
+        self.active.pop();
+        let previous_block = self.store.read_block(self.active.last().unwrap())?.unwrap();
+
+        Ok(std::mem::replace(&mut self.tip, previous_block))
+    }
+
+    /// Validates the block against the tip, updates the UTXO set with it, and makes it the tip.
+    fn connect(&mut self, block: Block) -> Result<(), Error> {
+        let hash = block.hash();
+
+        // the required bits can depend on the timestamp of the block.
+        let context = BlockContext { bits: self.next_bits(block.header.time), ..self.next_block_context() };
+
+        if let Err(error) = validation::validate_block(&block, &context, &self.utxos) {
+            return Err(Error::InvalidBlock { hash, error })
+        };
+
+        let undo = self.utxos.connect_block(&block, self.active.len() as u32)?;
+
+        self.active.push(hash);
+        self.undo.push(undo);
+        self.tip = block;
+
+        Ok(())
+    }
+}
+
+fn current_time() -> u32 {
+    SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap()
+        .as_secs() as u32
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Store(error) => write!(f, "{}", error),
+            Error::InvalidBlock { hash, error } => write!(f, "invalid block {}: {}", hex::encode(hash.to_be_bytes()), error),
+            Error::UnknownParent { hash } => write!(f, "parent of block {} is unknown", hex::encode(hash.to_be_bytes())),
+            Error::Utxo(error) => write!(f, "{}", error),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<blockstore::Error> for Error {
+    fn from(error: blockstore::Error) -> Error {
+        Error::Store(error)
+    }
+}
+
+impl From<utxo::Error> for Error {
+    fn from(error: utxo::Error) -> Error {
+        Error::Utxo(error)
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod test {
+    use super::*;
+    use crate::block;
+    use crate::blocktemplate;
+    use crate::blockstore::test::temp_data_dir;
+    use crate::mempool;
+    use crate::merkleroot;
+    use crate::miner;
+    use crate::script::opcodes::OP_1;
+    use crate::script::{Instruction, Script};
+    use crate::transactions::{OutPoint, Transaction, TxIn, TxOut};
+
+    use std::fs;
+
+    /// Easiest target of regtest, about every other hash is below it.
+    pub(crate) const EASY_BITS: u32 = 0x207fffff;
+
+    pub(crate) fn genesis() -> Block {
+        ChainParams::regtest().genesis
+    }
+
+    /// Loads a regtest chain, where blocks are easy to mine.
+    pub(crate) fn load(data_dir: &Path, reindex: bool) -> Chain {
+        Chain::load(&ChainParams::regtest(), data_dir, reindex).unwrap()
+    }
+
+    /// Mines a block timestamped `seconds` after `previous_block`. Different `seconds` tell apart
+    /// blocks mined on the same parent, and their coinbases.
+    pub(crate) fn mine(previous_block: &Block, seconds: u32) -> Block {
+        let context = BlockContext {
+            height: height_of(previous_block) + 1,
+            prev_block_hash: previous_block.hash(),
+            bits: EASY_BITS,
+            subsidy: 50 * validation::COIN,
+            median_time_past: 0,
+            now: 0,
+            enforce_bip34: true,
+        };
+
+        let mut tag = previous_block.hash().to_le_bytes().to_vec();
+        tag.extend(seconds.to_le_bytes());
+
+        let template = blocktemplate::new(context, &mempool::new());
+        let mut block = miner::new(Script::from(vec![0x51]), &tag).next(&template);
+        block.header.time = previous_block.header.time + seconds;
+
+        regrind(block)
+    }
+
+    /// The height of a block, from the BIP34 height of its coinbase.
+    fn height_of(block: &Block) -> u32 {
+        if block.header.prev_block_hash == u256::new(0) {
+            return 0
+        };
+
+        match block.transactions[0].inputs[0].script_sig.instructions().next() {
+            Some(Ok(Instruction::Op(op))) => (op - OP_1 + 1) as u32,
+            Some(Ok(Instruction::PushBytes(bytes))) => bytes.iter().rev().fold(0, |height, byte| (height << 8) | *byte as u32),
+            _ => panic!("coinbase without height"),
+        }
+    }
+
+    /// Updates the merkle root of the block, and finds a new valid nounce.
+    pub(crate) fn regrind(mut block: Block) -> Block {
+        let merkle_root = hex::decode(merkleroot::from_transactions(&block.transactions)).unwrap();
+        block.header.merkle_root = u256::from_le_bytes(merkle_root.try_into().unwrap());
+        block.header.nounce = 0;
+
+        while block.hash() > difficulty::bits_to_target(block.header.bits) {
+            block.header.nounce += 1;
+        };
+
+        block
+    }
+
+    #[test]
+    fn load_empty_data_dir_test() {
+        let data_dir = temp_data_dir("chain-empty");
+        let chain = Chain::load(&ChainParams::mainnet(), &data_dir, false).unwrap();
+
+        assert_eq!(0, chain.height());
+        assert_eq!(&block::genesis(), chain.tip());
+        assert!(chain.contains(&block::genesis().hash()));
+    }
+
+    #[test]
+    fn resume_chain_test() {
+        let data_dir = temp_data_dir("chain-resume");
+
+        let mut chain = load(&data_dir, false);
+        for time in 1..=3 {
+            let block = mine(chain.tip(), time);
+            chain.add_block(block).unwrap();
+        };
+        let tip = chain.tip().clone();
+        drop(chain);
+
+        let chain = load(&data_dir, false);
+        assert_eq!(3, chain.height());
+        assert_eq!(&tip, chain.tip());
+        assert_eq!(Some(genesis().hash()), chain.hash_at(0));
+        assert_eq!(Some(tip.hash()), chain.hash_at(3));
+    }
+
+    #[test]
+    fn utxos_test() {
+        let data_dir = temp_data_dir("chain-utxos");
+
+        let mut chain = load(&data_dir, false);
+        assert!(chain.utxos().is_empty());
+
+        let block1 = mine(chain.tip(), 1);
+        let block2 = mine(&block1, 2);
+        chain.add_block(block1.clone()).unwrap();
+        chain.add_block(block2.clone()).unwrap();
+
+        let coinbase = OutPoint { txid: block2.transactions[0].txid(), vout: 0 };
+        assert_eq!(2, chain.utxos().get(&coinbase).unwrap().height);
+        drop(chain);
+
+        // the UTXO set is rebuilt on load.
+        let mut chain = load(&data_dir, false);
+        assert_eq!(2, chain.utxos().len());
+        assert!(chain.utxos().contains(&coinbase));
+
+        assert_eq!(block2, chain.disconnect_tip().unwrap());
+        assert_eq!(&block1, chain.tip());
+        assert_eq!(1, chain.height());
+        assert!(!chain.utxos().contains(&coinbase));
+    }
+
+    #[test]
+    fn reindex_test() {
+        let data_dir = temp_data_dir("chain-reindex");
+
+        let mut chain = load(&data_dir, false);
+        chain.add_block(mine(chain.tip(), 1)).unwrap();
+        chain.add_block(mine(chain.tip(), 2)).unwrap();
+        let tip = chain.tip().clone();
+        drop(chain);
+
+        fs::remove_file(data_dir.join("regtest").join("blocks").join("index.dat")).unwrap();
+
+        let chain = load(&data_dir, true);
+        assert_eq!(2, chain.height());
+        assert_eq!(&tip, chain.tip());
+    }
+
+    #[test]
+    fn add_block_test() {
+        let data_dir = temp_data_dir("chain-add-block");
+        let mut chain = load(&data_dir, false);
+
+        let block = mine(chain.tip(), 1);
+        chain.add_block(block.clone()).unwrap();
+
+        let orphan = mine(&mine(&block, 2), 3);
+        assert!(matches!(chain.add_block(orphan), Err(Error::UnknownParent { .. })));
+
+        let mut invalid = mine(chain.tip(), 3);
+        invalid.transactions[0].outputs[0].value += 1;
+
+        assert!(matches!(
+            chain.add_block(invalid),
+            Err(Error::InvalidBlock { error: BlockError::BadMerkleRoot, .. })
+        ));
+
+        let mut too_old = mine(chain.tip(), 4);
+        too_old.header.time = chain.median_time_past();
+        let too_old = regrind(too_old);
+
+        assert!(matches!(
+            chain.add_block(too_old.clone()),
+            Err(Error::InvalidBlock { error: BlockError::TimeTooOld, .. })
+        ));
+        assert_eq!(1, chain.height());
+
+        // blocks on an invalid block are rejected.
+        assert!(matches!(
+            chain.add_block(mine(&too_old, 1)),
+            Err(Error::InvalidBlock { error: BlockError::BadPrevBlock, .. })
+        ));
+    }
+
+    #[test]
+    fn skip_invalid_blocks_test() {
+        let data_dir = temp_data_dir("chain-invalid");
+
+        let block = mine(&genesis(), 1);
+        let mut invalid = mine(&block, 2);
+        invalid.transactions[0].outputs[0].value += 1;
+        let on_invalid = mine(&invalid, 3);
+
+        let mut store = BlockStore::open(&data_dir.join("regtest"), ChainParams::regtest().magic).unwrap();
+        for block in [&genesis(), &block, &invalid, &on_invalid] {
+            store.write_block(block).unwrap();
+        };
+        drop(store);
+
+        let chain = load(&data_dir, false);
+        assert_eq!(1, chain.height());
+        assert_eq!(&block, chain.tip());
+    }
+
+    #[test]
+    fn longest_fork_test() {
+        let data_dir = temp_data_dir("chain-fork");
+
+        let a1 = mine(&genesis(), 1);
+        let a2 = mine(&a1, 2);
+        let b1 = mine(&genesis(), 3);
+        let b2 = mine(&b1, 4);
+        let b3 = mine(&b2, 5);
+
+        let mut store = BlockStore::open(&data_dir.join("regtest"), ChainParams::regtest().magic).unwrap();
+        for block in [&genesis(), &a1, &b1, &b2, &a2, &b3] {
+            store.write_block(block).unwrap();
+        };
+        drop(store);
+
+        let chain = load(&data_dir, false);
+        assert_eq!(3, chain.height());
+        assert_eq!(&b3, chain.tip());
+        assert_eq!(Some(b1.hash()), chain.hash_at(1));
+    }
+
+    #[test]
+    fn reorg_test() {
+        let data_dir = temp_data_dir("chain-reorg");
+        let mut chain = load(&data_dir, false);
+
+        let a1 = mine(&genesis(), 1);
+        let a2 = mine(&a1, 1);
+        let b1 = mine(&genesis(), 2);
+        let b2 = mine(&b1, 2);
+        let b3 = mine(&b2, 2);
+
+        chain.add_block(a1.clone()).unwrap();
+        chain.add_block(a2.clone()).unwrap();
+        let chainwork = chain.chainwork();
+
+        // a branch with less or as much work doesn't replace the active chain.
+        chain.add_block(b1.clone()).unwrap();
+        chain.add_block(b2.clone()).unwrap();
+        assert_eq!(&a2, chain.tip());
+        assert!(chain.contains(&b2.hash()));
+
+        chain.add_block(b3.clone()).unwrap();
+        assert_eq!(&b3, chain.tip());
+        assert_eq!(3, chain.height());
+        assert_eq!(chainwork + difficulty::work_from_bits(EASY_BITS), chain.chainwork());
+        assert_eq!(Some(b1.hash()), chain.hash_at(1));
+
+        let coinbase = |block: &Block| OutPoint { txid: block.transactions[0].txid(), vout: 0 };
+        assert!(!chain.utxos().contains(&coinbase(&a1)));
+        assert!(!chain.utxos().contains(&coinbase(&a2)));
+        assert!(chain.utxos().contains(&coinbase(&b1)));
+        assert_eq!(3, chain.utxos().len());
+    }
+
+    #[test]
+    fn reorg_to_invalid_branch_test() {
+        let data_dir = temp_data_dir("chain-reorg-invalid");
+        let mut chain = load(&data_dir, false);
+
+        let a1 = mine(&genesis(), 1);
+        let a2 = mine(&a1, 1);
+        chain.add_block(a1.clone()).unwrap();
+        chain.add_block(a2.clone()).unwrap();
+
+        // b2 spends an output that doesn't exist, which is only found out when connecting it.
+        let b1 = mine(&genesis(), 2);
+        let mut b2 = mine(&b1, 2);
+        let missing = OutPoint { txid: u256::new(1), vout: 0 };
+        b2.transactions.push(Transaction {
+            version: 1,
+            inputs: vec![TxIn { txid: missing.txid, vout: missing.vout, script_sig: Script::new(), sequence: 0xffffffff, witness: vec![] }],
+            outputs: vec![TxOut { value: 1, script_pub_key: Script::new() }],
+            lock_time: 0,
+        });
+        let b2 = regrind(b2);
+        let b3 = mine(&b2, 2);
+
+        chain.add_block(b1.clone()).unwrap();
+        chain.add_block(b2.clone()).unwrap();
+
+        let result = chain.add_block(b3.clone());
+        assert!(matches!(
+            result,
+            Err(Error::InvalidBlock { hash, error: BlockError::MissingInput(outpoint) }) if hash == b2.hash() && outpoint == missing
+        ));
+
+        // back on the original branch.
+        assert_eq!(&a2, chain.tip());
+        assert_eq!(Some(a1.hash()), chain.hash_at(1));
+        assert!(chain.utxos().contains(&OutPoint { txid: a1.transactions[0].txid(), vout: 0 }));
+        assert!(!chain.utxos().contains(&OutPoint { txid: b1.transactions[0].txid(), vout: 0 }));
+
+        // the invalid branch is left out on load too.
+        drop(chain);
+        let chain = load(&data_dir, false);
+        assert_eq!(&a2, chain.tip());
+    }
+
+    #[test]
+    fn next_bits_test() {
+        let data_dir = temp_data_dir("chain-next-bits");
+        let mut chain = load(&data_dir, false);
+        assert_eq!(EASY_BITS, chain.next_bits(0));
+
+        chain.add_block(mine(chain.tip(), 1)).unwrap();
+        assert_eq!(EASY_BITS, chain.next_bits(0));
+
+        let mut harder = mine(chain.tip(), 1);
+        harder.header.bits = 0x2000ffff;
+        assert!(matches!(
+            chain.add_block(regrind(harder)),
+            Err(Error::InvalidBlock { error: BlockError::BadBits(0x2000ffff), .. })
+        ));
+    }
+
+    #[test]
+    fn min_difficulty_blocks_test() {
+        // an easier limit than the regtest bits, so min difficulty blocks tell apart.
+        let params = ChainParams { pow_limit: u256::MAX, ..ChainParams::regtest() };
+        let limit_bits = difficulty::target_to_bits(u256::MAX);
+
+        let data_dir = temp_data_dir("chain-min-difficulty");
+        let mut chain = Chain::load(&params, &data_dir, false).unwrap();
+        let time = chain.tip().header.time;
+        assert_eq!(EASY_BITS, chain.next_bits(time + 20 * 60));
+        assert_eq!(limit_bits, chain.next_bits(time + 20 * 60 + 1));
+
+        let mut too_easy = mine(chain.tip(), 20 * 60);
+        too_easy.header.bits = limit_bits;
+        assert!(matches!(
+            chain.add_block(regrind(too_easy)),
+            Err(Error::InvalidBlock { error: BlockError::BadBits(_), .. })
+        ));
+
+        let mut easy = mine(chain.tip(), 20 * 60 + 1);
+        easy.header.bits = limit_bits;
+        chain.add_block(regrind(easy)).unwrap();
+        assert_eq!(1, chain.height());
+
+        // the next blocks go back to the bits of genesis.
+        let time = chain.tip().header.time;
+        assert_eq!(EASY_BITS, chain.next_bits(time + 600));
+        assert_eq!(limit_bits, chain.next_bits(time + 20 * 60 + 1));
+    }
+}
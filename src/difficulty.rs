@@ -1,16 +1,91 @@
 use crate::u256;
 
+/// Number of blocks between two difficulty adjustments.
+pub const RETARGET_INTERVAL: usize = 2016;
+
+/// Expected time to mine `RETARGET_INTERVAL` blocks: two weeks, one block every 10 minutes.
+pub const TARGET_TIMESPAN: u32 = 14 * 24 * 60 * 60;
+
+/// Expected time between two blocks.
+pub const TARGET_SPACING: u32 = 10 * 60;
+
+// Bits are the compact form of a target, a floating point number of base 256: the highest byte
+// is the exponent, and the 3 others are the coefficient. Like bitcoin core, the highest bit of
+// the coefficient is a sign bit, so targets are never negative.
+//
+// https://learnmeabitcoin.com/technical/bits
+
 pub fn bits_to_target(bits: u32) -> u256 {
     // Target = coefficient * 2 ^ ( 8 * (index — 3) )
     let exponent = bits >> 24;
-    let coefficient = u256::new((bits & 0x00ffffff).into());
+    let coefficient = u256::new((bits & 0x007fffff).into());
+
+    // a negative target, or a target above 2^256, is invalid: no hash is below it.
+    let negative = bits & 0x00800000 != 0 && coefficient != 0;
+    let overflow = coefficient != 0 && exponent > 3 && (exponent - 3) * 8 > coefficient.leading_zeros();
+    if negative || overflow {
+        return u256::new(0)
+    };
+
+    if exponent <= 3 {
+        coefficient >> ((3 - exponent) * 8)
+    } else {
+        coefficient << ((exponent - 3) * 8)
+    }
+}
+
+pub fn target_to_bits(target: u256) -> u32 {
+    let mut exponent = (256 - target.leading_zeros()).div_ceil(8);
+
+    let mut coefficient = match exponent <= 3 {
+        true => (target << ((3 - exponent) * 8)).as_u32(),
+        false => (target >> ((exponent - 3) * 8)).as_u32(),
+    };
+
+    // the coefficient would be negative, use one more byte of exponent instead.
+    if coefficient & 0x00800000 != 0 {
+        coefficient >>= 8;
+        exponent += 1;
+    };
 
-    coefficient << ((exponent - 3) * 8)
+    coefficient | (exponent << 24)
+}
+
+/// The bits of the next `RETARGET_INTERVAL` blocks, from the bits of the last interval and the
+/// timestamps of its first and last blocks. The target moves by at most a factor of 4, and is
+/// never easier than `pow_limit`.
+pub fn retarget(bits: u32, first_block_time: u32, last_block_time: u32, pow_limit: u256) -> u32 {
+    let timespan = last_block_time.saturating_sub(first_block_time)
+        .clamp(TARGET_TIMESPAN / 4, TARGET_TIMESPAN * 4);
+
+    let target = bits_to_target(bits)
+        .checked_mul(u256::from(timespan))
+        .map(|target| target / u256::from(TARGET_TIMESPAN))
+        .unwrap_or(pow_limit);
+
+    target_to_bits(target.min(pow_limit))
+}
+
+/// The expected number of hashes needed to find a block below the target of `bits`, i.e.
+/// 2^256 / (target + 1). Like bitcoin core, computed as ~target / (target + 1) + 1, since 2^256
+/// doesn't fit in a u256.
+pub fn work_from_bits(bits: u32) -> u256 {
+    let target = bits_to_target(bits);
+    if target == 0 {
+        return u256::new(0)
+    };
+
+    (!target / (target + 1)) + 1
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
+    use crate::params::ChainParams;
+
+    fn pow_limit() -> u256 {
+        ChainParams::mainnet().pow_limit
+    }
 
     #[test]
     fn bits_to_target_test() {
@@ -26,4 +101,63 @@ mod test {
 
         assert_eq!(target, bits_to_target(0x180696f4));
     }
+
+    #[test]
+    fn work_from_bits_test() {
+        // genesis block work, see the chainwork of block 0 in bitcoin core.
+        assert_eq!(u256::new(0x100010001), work_from_bits(0x1d00ffff));
+
+        // regtest, about every other hash is below the target.
+        assert_eq!(u256::new(2), work_from_bits(0x207fffff));
+    }
+
+    #[test]
+    fn bits_to_target_edge_cases_test() {
+        assert_eq!(u256::new(0x12), bits_to_target(0x01123456));
+        assert_eq!(u256::new(0x1234), bits_to_target(0x02123456));
+        assert_eq!(u256::new(0x123456), bits_to_target(0x03123456));
+
+        // the coefficient exactly fills the 256 bits.
+        assert_eq!(u256::new(0xff) << 248, bits_to_target(0x220000ff));
+        assert_eq!(u256::new(0), bits_to_target(0x230000ff));
+
+        // negative and overflowing targets.
+        assert_eq!(u256::new(0), bits_to_target(0x04923456));
+        assert_eq!(u256::new(0), bits_to_target(0xff123456));
+        assert_eq!(u256::new(0), work_from_bits(0x04923456));
+    }
+
+    #[test]
+    fn target_to_bits_test() {
+        assert_eq!(0x1d00ffff, target_to_bits(pow_limit()));
+        assert_eq!(0x1d00ffff, target_to_bits(bits_to_target(0x1d00ffff)));
+        assert_eq!(0x180696f4, target_to_bits(bits_to_target(0x180696f4)));
+        assert_eq!(0x207fffff, target_to_bits(bits_to_target(0x207fffff)));
+
+        assert_eq!(0, target_to_bits(u256::new(0)));
+        assert_eq!(0x01120000, target_to_bits(u256::new(0x12)));
+        assert_eq!(0x04123456, target_to_bits(u256::new(0x12345600)));
+
+        // 0x80 would set the sign bit of the coefficient.
+        assert_eq!(0x02008000, target_to_bits(u256::new(0x80)));
+    }
+
+    // test cases from bitcoin core src/test/pow_tests.cpp
+    #[test]
+    fn retarget_test() {
+        // block 32255
+        assert_eq!(0x1d00d86a, retarget(0x1d00ffff, 1261130161, 1262152739, pow_limit()));
+
+        // blocks 0 to 2015, the target can't go above the proof of work limit.
+        assert_eq!(0x1d00ffff, retarget(0x1d00ffff, 1231006505, 1233061996, pow_limit()));
+    }
+
+    #[test]
+    fn retarget_clamp_test() {
+        // block 68543, the window was much shorter than 2 weeks.
+        assert_eq!(0x1c0168fd, retarget(0x1c05a3f4, 1279008237, 1279297671, pow_limit()));
+
+        // block 46367, the window was much longer than 2 weeks.
+        assert_eq!(0x1d00e1fd, retarget(0x1c387f6f, 1263163443, 1269211443, pow_limit()));
+    }
 }
@@ -0,0 +1,327 @@
+// Consensus encoding of the values sent on the wire and written to disk.
+//
+// Every type implements `Encodable` and `Decodable` on top of `std::io::Write` and `std::io::Read`,
+// so they can be nested in each other: a block encodes its header, then a list of transactions,
+// which encode their inputs and outputs, etc.
+
+use std::fmt;
+use std::io::{self, Read, Write};
+
+use crate::transactions::{Transaction, TxIn, TxOut};
+use crate::u256;
+
+/// Maximum size of a vector, or number of items in a list, that will be decoded. Avoids
+/// allocating absurd amounts of memory for a bogus length prefix.
+pub const MAX_VEC_SIZE: u64 = 0x02000000;
+
+pub trait Encodable {
+    /// Writes the value to `writer`, returning the number of bytes written.
+    fn consensus_encode<W: Write + ?Sized>(&self, writer: &mut W) -> Result<usize, Error>;
+}
+
+pub trait Decodable: Sized {
+    fn consensus_decode<R: Read + ?Sized>(reader: &mut R) -> Result<Self, Error>;
+}
+
+pub fn serialize<T: Encodable + ?Sized>(value: &T) -> Vec<u8> {
+    let mut payload: Vec<u8> = Vec::new();
+    value.consensus_encode(&mut payload).unwrap();
+    payload
+}
+
+pub fn serialize_hex<T: Encodable + ?Sized>(value: &T) -> String {
+    hex::encode(serialize(value))
+}
+
+/// Decodes a value from `bytes`, failing if any bytes are left once it's read.
+pub fn deserialize<T: Decodable>(bytes: &[u8]) -> Result<T, Error> {
+    let mut reader = bytes;
+    let value = T::consensus_decode(&mut reader)?;
+
+    match reader.len() {
+        0 => Ok(value),
+        n => Err(Error::TrailingBytes(n)),
+    }
+}
+
+pub fn deserialize_hex<T: Decodable>(value_hex: &str) -> Result<T, Error> {
+    let bytes = hex::decode(value_hex).map_err(|_| Error::InvalidHex)?;
+    deserialize(&bytes)
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum Error {
+    /// The data ended before the value could be fully read.
+    UnexpectedEnd,
+    /// Reading or writing the data failed.
+    Io(io::ErrorKind),
+    /// A CompactSize integer wasn't encoded with its smallest representation.
+    NonCanonicalCompactSize,
+    /// A length prefix is larger than `MAX_VEC_SIZE`.
+    OversizedVector(u64),
+    /// Some bytes were left after the value was read.
+    TrailingBytes(usize),
+    /// The data isn't a valid hex string.
+    InvalidHex,
+    /// A segwit transaction was serialized with the witness flag, but none of its inputs have a
+    /// witness.
+    SuperfluousWitness,
+    /// A transaction was serialized with optional data flags that aren't known.
+    UnknownTransactionFlags(u8),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::UnexpectedEnd => write!(f, "unexpected end of data"),
+            Error::Io(kind) => write!(f, "i/o error: {}", kind),
+            Error::NonCanonicalCompactSize => write!(f, "non-canonical CompactSize integer"),
+            Error::OversizedVector(n) => write!(f, "vector of {} items is too large", n),
+            Error::TrailingBytes(n) => write!(f, "{} trailing bytes after the value", n),
+            Error::InvalidHex => write!(f, "invalid hex string"),
+            Error::SuperfluousWitness => write!(f, "superfluous witness record"),
+            Error::UnknownTransactionFlags(flags) => write!(f, "unknown transaction optional data flags: {:#04x}", flags),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<io::Error> for Error {
+    fn from(error: io::Error) -> Error {
+        match error.kind() {
+            io::ErrorKind::UnexpectedEof => Error::UnexpectedEnd,
+            kind => Error::Io(kind),
+        }
+    }
+}
+
+macro_rules! impl_int_encodable {
+    ($($int:ty),*) => {$(
+        impl Encodable for $int {
+            fn consensus_encode<W: Write + ?Sized>(&self, writer: &mut W) -> Result<usize, Error> {
+                let bytes = self.to_le_bytes();
+                writer.write_all(&bytes)?;
+                Ok(bytes.len())
+            }
+        }
+
+        impl Decodable for $int {
+            fn consensus_decode<R: Read + ?Sized>(reader: &mut R) -> Result<Self, Error> {
+                let mut bytes = [0; std::mem::size_of::<$int>()];
+                reader.read_exact(&mut bytes)?;
+                Ok(<$int>::from_le_bytes(bytes))
+            }
+        }
+    )*};
+}
+
+impl_int_encodable!(u8, u16, u32, u64, i32, i64, u256);
+
+// https://en.bitcoin.it/wiki/Protocol_documentation#Variable_length_integer
+//
+// CompactSize integers are used to prefix every variable length field on the wire: input and
+// output counts, script lengths, etc.
+//
+//  value               | storage
+//  --------------------|----------------------------
+//  < 0xfd              | 1 byte
+//  <= 0xffff           | 0xfd followed by a u16 (le)
+//  <= 0xffffffff       | 0xfe followed by a u32 (le)
+//  > 0xffffffff        | 0xff followed by a u64 (le)
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CompactSize(pub u64);
+
+impl CompactSize {
+    /// Number of bytes taken by the encoded integer.
+    pub fn size(&self) -> usize {
+        match self.0 {
+            0..=0xfc => 1,
+            0xfd..=0xffff => 3,
+            0x10000..=0xffffffff => 5,
+            _ => 9,
+        }
+    }
+}
+
+impl Encodable for CompactSize {
+    fn consensus_encode<W: Write + ?Sized>(&self, writer: &mut W) -> Result<usize, Error> {
+        let n = self.0;
+
+        match n {
+            0..=0xfc => (n as u8).consensus_encode(writer)?,
+            0xfd..=0xffff => 0xfdu8.consensus_encode(writer)? + (n as u16).consensus_encode(writer)?,
+            0x10000..=0xffffffff => 0xfeu8.consensus_encode(writer)? + (n as u32).consensus_encode(writer)?,
+            _ => 0xffu8.consensus_encode(writer)? + n.consensus_encode(writer)?,
+        };
+
+        Ok(self.size())
+    }
+}
+
+impl Decodable for CompactSize {
+    // values that weren't encoded with their smallest possible representation are rejected.
+    fn consensus_decode<R: Read + ?Sized>(reader: &mut R) -> Result<Self, Error> {
+        let (n, min) = match u8::consensus_decode(reader)? {
+            0xfd => (u16::consensus_decode(reader)? as u64, 0xfd),
+            0xfe => (u32::consensus_decode(reader)? as u64, 0x10000),
+            0xff => (u64::consensus_decode(reader)?, 0x100000000),
+            n => (n as u64, 0),
+        };
+
+        if n < min {
+            return Err(Error::NonCanonicalCompactSize)
+        };
+
+        Ok(CompactSize(n))
+    }
+}
+
+/// Reads a CompactSize length prefix, making sure it isn't larger than `MAX_VEC_SIZE`.
+pub fn decode_length<R: Read + ?Sized>(reader: &mut R) -> Result<u64, Error> {
+    let CompactSize(n) = CompactSize::consensus_decode(reader)?;
+
+    if n > MAX_VEC_SIZE {
+        return Err(Error::OversizedVector(n))
+    };
+
+    Ok(n)
+}
+
+// byte vectors are prefixed by their length.
+impl Encodable for Vec<u8> {
+    fn consensus_encode<W: Write + ?Sized>(&self, writer: &mut W) -> Result<usize, Error> {
+        let size = CompactSize(self.len() as u64).consensus_encode(writer)?;
+        writer.write_all(self)?;
+        Ok(size + self.len())
+    }
+}
+
+impl Decodable for Vec<u8> {
+    fn consensus_decode<R: Read + ?Sized>(reader: &mut R) -> Result<Self, Error> {
+        let size = decode_length(reader)?;
+
+        // read through `take` rather than allocating `size` bytes upfront.
+        let mut bytes = vec![];
+        reader.take(size).read_to_end(&mut bytes)?;
+
+        if bytes.len() as u64 != size {
+            return Err(Error::UnexpectedEnd)
+        };
+
+        Ok(bytes)
+    }
+}
+
+// lists are prefixed by their number of items.
+macro_rules! impl_vec_encodable {
+    ($($item:ty),*) => {$(
+        impl Encodable for Vec<$item> {
+            fn consensus_encode<W: Write + ?Sized>(&self, writer: &mut W) -> Result<usize, Error> {
+                let mut size = CompactSize(self.len() as u64).consensus_encode(writer)?;
+                for item in self {
+                    size += item.consensus_encode(writer)?;
+                };
+                Ok(size)
+            }
+        }
+
+        impl Decodable for Vec<$item> {
+            fn consensus_decode<R: Read + ?Sized>(reader: &mut R) -> Result<Self, Error> {
+                let count = decode_length(reader)?;
+
+                let mut items = vec![];
+                for _ in 0..count {
+                    items.push(<$item>::consensus_decode(reader)?);
+                };
+                Ok(items)
+            }
+        }
+    )*};
+}
+
+impl_vec_encodable!(Vec<u8>, TxIn, TxOut, Transaction);
+
+impl<A: Encodable, B: Encodable> Encodable for (A, B) {
+    fn consensus_encode<W: Write + ?Sized>(&self, writer: &mut W) -> Result<usize, Error> {
+        Ok(self.0.consensus_encode(writer)? + self.1.consensus_encode(writer)?)
+    }
+}
+
+impl<A: Decodable, B: Decodable> Decodable for (A, B) {
+    fn consensus_decode<R: Read + ?Sized>(reader: &mut R) -> Result<Self, Error> {
+        Ok((A::consensus_decode(reader)?, B::consensus_decode(reader)?))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn compact_size_encode_test() {
+        assert_eq!("00", serialize_hex(&CompactSize(0)));
+        assert_eq!("fc", serialize_hex(&CompactSize(0xfc)));
+        assert_eq!("fdfd00", serialize_hex(&CompactSize(0xfd)));
+        assert_eq!("fdffff", serialize_hex(&CompactSize(0xffff)));
+        assert_eq!("fe00000100", serialize_hex(&CompactSize(0x10000)));
+        assert_eq!("feffffffff", serialize_hex(&CompactSize(0xffffffff)));
+        assert_eq!("ff0000000001000000", serialize_hex(&CompactSize(0x100000000)));
+        assert_eq!("ffffffffffffffffff", serialize_hex(&CompactSize(u64::MAX)));
+    }
+
+    #[test]
+    fn compact_size_decode_test() {
+        for n in [0, 0xfc, 0xfd, 0xffff, 0x10000, 0xffffffff, 0x100000000, u64::MAX] {
+            let bytes = serialize(&CompactSize(n));
+            assert_eq!(bytes.len(), CompactSize(n).size());
+            assert_eq!(Ok(CompactSize(n)), deserialize(&bytes));
+        }
+    }
+
+    #[test]
+    fn compact_size_decode_truncated_test() {
+        assert_eq!(Err(Error::UnexpectedEnd), deserialize::<CompactSize>(&[]));
+        assert_eq!(Err(Error::UnexpectedEnd), deserialize_hex::<CompactSize>("fd00"));
+        assert_eq!(Err(Error::UnexpectedEnd), deserialize_hex::<CompactSize>("fe000001"));
+        assert_eq!(Err(Error::UnexpectedEnd), deserialize_hex::<CompactSize>("ff00000000010000"));
+    }
+
+    #[test]
+    fn compact_size_decode_non_canonical_test() {
+        assert_eq!(Err(Error::NonCanonicalCompactSize), deserialize_hex::<CompactSize>("fdfc00"));
+        assert_eq!(Err(Error::NonCanonicalCompactSize), deserialize_hex::<CompactSize>("feffff0000"));
+        assert_eq!(Err(Error::NonCanonicalCompactSize), deserialize_hex::<CompactSize>("ffffffffff00000000"));
+    }
+
+    #[test]
+    fn int_encoding_test() {
+        assert_eq!("01000000", serialize_hex(&1u32));
+        assert_eq!("feffffffffffffff", serialize_hex(&-2i64));
+        assert_eq!(Ok(0xdeadbeef), deserialize_hex::<u32>("efbeadde"));
+        assert_eq!(Err(Error::UnexpectedEnd), deserialize_hex::<u64>("efbeadde"));
+        assert_eq!(Err(Error::TrailingBytes(1)), deserialize_hex::<u16>("efbead"));
+
+        let hash = u256::from_str_radix("000000000019d6689c085ae165831e934ff763ae46a2a6c172b3f1b60a8ce26f", 16).unwrap();
+        assert_eq!("6fe28c0ab6f1b372c1a6a246ae63f74f931e8365e15a089c68d6190000000000", serialize_hex(&hash));
+        assert_eq!(Ok(hash), deserialize(&serialize(&hash)));
+    }
+
+    #[test]
+    fn bytes_encoding_test() {
+        assert_eq!("03abcdef", serialize_hex(&vec![0xabu8, 0xcd, 0xef]));
+        assert_eq!(Ok(vec![0xab, 0xcd, 0xef]), deserialize_hex::<Vec<u8>>("03abcdef"));
+        assert_eq!(Err(Error::UnexpectedEnd), deserialize_hex::<Vec<u8>>("04abcdef"));
+
+        let stack = vec![vec![0xab], vec![]];
+        assert_eq!("0201ab00", serialize_hex(&stack));
+        assert_eq!(Ok(stack), deserialize_hex::<Vec<Vec<u8>>>("0201ab00"));
+    }
+
+    #[test]
+    fn oversized_vector_test() {
+        // announces 0xffffffffffffffff bytes, but only has 2.
+        assert_eq!(Err(Error::OversizedVector(u64::MAX)), deserialize_hex::<Vec<u8>>("ffffffffffffffffffabcd"));
+        assert_eq!(Err(Error::OversizedVector(u64::MAX)), deserialize_hex::<Vec<TxOut>>("ffffffffffffffffff"));
+    }
+}
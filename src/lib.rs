@@ -3,11 +3,21 @@ pub type u256 = ethnum::u256;
 
 pub mod addresses;
 pub mod block;
+pub mod blockstore;
+pub mod blocktemplate;
+pub mod chain;
 pub mod difficulty;
+pub mod encoding;
 pub mod hash;
 pub mod keys;
+pub mod mempool;
 pub mod merkleroot;
 pub mod miner;
+pub mod orphanage;
+pub mod params;
+pub mod policy;
 pub mod script;
 pub mod serializers;
 pub mod transactions;
+pub mod utxo;
+pub mod validation;
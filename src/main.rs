@@ -1,7 +1,10 @@
 use std::fs;
-use std::path::Path;
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::{Duration, Instant};
 
 use clap::{Args, Parser, Subcommand};
+use rcoin::params::{ChainParams, Network};
 
 #[derive(Parser)]
 #[command(name = "rcoin")]
@@ -9,6 +12,10 @@ use clap::{Args, Parser, Subcommand};
 #[command(version = "0.0")]
 #[command(about = "client to interact with the rcoin network.", long_about = None)]
 struct Cli {
+    /// Network to use: main, test, signet or regtest.
+    #[arg(long, global = true, default_value = "main")]
+    chain: Network,
+
     #[command(subcommand)]
     commands: Commands
 }
@@ -17,6 +24,12 @@ struct Cli {
 enum Commands {
     Wallet(Wallet),
     Miner(Miner),
+    /// Measure the hashrate of one mining thread.
+    Bench {
+        /// How long each measure lasts.
+        #[arg(long, default_value = "5")]
+        seconds: u64,
+    },
 }
 
 #[derive(Args)]
@@ -34,25 +47,65 @@ enum WalletCommands {
 
 #[derive(Args)]
 struct Miner {
-    address: String
+    /// Address the block rewards are paid to.
+    address: String,
+
+    /// Message written in the coinbase of the mined blocks, after the block height.
+    #[arg(long, default_value = "rcoin miner")]
+    tag: String,
+
+    /// Directory where the mined blocks are written. Defaults to ~/.rcoin
+    #[arg(long)]
+    datadir: Option<PathBuf>,
+
+    /// Rebuild the block index from the block files before loading the chain.
+    #[arg(long)]
+    reindex: bool,
+
+    /// Number of mining threads. Defaults to the number of cores.
+    #[arg(long)]
+    threads: Option<usize>,
+
+    /// Stop after mining this many blocks.
+    #[arg(long)]
+    blocks: Option<u64>,
+
+    /// Mine at these bits (in hex, e.g. 1d00ffff) instead of the ones the chain requires. The
+    /// blocks found aren't added to the chain. Use --chain regtest for valid easy blocks.
+    #[arg(long, value_parser = parse_bits)]
+    bits: Option<u32>,
+
+    /// Seconds between two status lines while mining.
+    #[arg(long, default_value = "5")]
+    status_interval: u64,
+
+    /// Print every block found as JSON.
+    #[arg(long)]
+    json: bool,
+}
+
+fn parse_bits(bits: &str) -> Result<u32, String> {
+    u32::from_str_radix(bits.trim_start_matches("0x"), 16).map_err(|error| format!("invalid bits: {}", error))
 }
 
 fn main() {
     let cli = Cli::parse();
+    let params = ChainParams::from_network(cli.chain);
 
     match &cli.commands {
         Commands::Wallet(wallet) => {
             match &wallet.commands {
-                WalletCommands::New{ path } => create_wallet(path),
-                WalletCommands::Keys{ path } => show_wallet_keys(path),
+                WalletCommands::New{ path } => create_wallet(path, &params),
+                WalletCommands::Keys{ path } => show_wallet_keys(path, &params),
             }
         },
-        Commands::Miner(miner) => start_miner(miner),
+        Commands::Miner(miner) => start_miner(miner, &params),
+        Commands::Bench{ seconds } => bench(Duration::from_secs(*seconds)),
     }
 
 }
 
-fn create_wallet(path: &String) {
+fn create_wallet(path: &String, params: &ChainParams) {
     if let Err(error) = fs::create_dir(path) {
         println!("could not create wallet at: {}", path);
         println!("{}", error);
@@ -72,48 +125,156 @@ fn create_wallet(path: &String) {
         return
     }
 
-    println!("Rcoin address: {}", rcoin::addresses::from_keychain(&keychain));
+    println!("Rcoin address: {}", rcoin::addresses::from_keychain(&keychain, params));
     println!("==================================");
     println!("private key:\t{}", keychain.private_key_hex());
     println!("public key:\t{}", keychain.public_key_hex());
 }
 
-fn show_wallet_keys(path: &String) {
+fn show_wallet_keys(path: &String, params: &ChainParams) {
     let path = Path::new(path);
     let private_key_pem = fs::read(path.join("key")).expect("could not read private key file.");
     let keychain = rcoin::keys::Keychain::from_pem(&private_key_pem);
 
-    println!("Rcoin address: {}", rcoin::addresses::from_keychain(&keychain));
+    println!("Rcoin address: {}", rcoin::addresses::from_keychain(&keychain, params));
     println!("==================================");
     println!("private key:\t{}", keychain.private_key_hex());
     println!("public key:\t{}", keychain.public_key_hex());
 }
 
-fn start_miner(miner: &Miner) {
+fn start_miner(miner: &Miner, params: &ChainParams) {
+    let script_pub_key = match rcoin::addresses::to_script(&miner.address, params) {
+        Ok(script) => script,
+        Err(error) => {
+            println!("could not mine to address: {}", miner.address);
+            println!("{}", error);
+            return
+        }
+    };
+
+    if miner.tag.len() > rcoin::miner::MAX_TAG_SIZE {
+        println!("the coinbase tag can't be longer than {} bytes", rcoin::miner::MAX_TAG_SIZE);
+        return
+    }
+
     println!("miner started for address: {}", miner.address);
 
-    // 1. define a target u256 number for our blocks. (challenge is to generate a hash smaller than that)
-    // 2. generate blocks until one of them has a hash smaller than that target.
-    // 3. print that u256 value, its hash, and its details.
-    let target_bits = 0x1e0696f4;
-    let target = rcoin::difficulty::bits_to_target(target_bits);
+    let data_dir = miner.datadir.clone().unwrap_or_else(rcoin::blockstore::default_data_dir);
+    let mut chain = match rcoin::chain::Chain::load(params, &data_dir, miner.reindex) {
+        Ok(chain) => chain,
+        Err(error) => {
+            println!("could not load the chain from: {}", data_dir.display());
+            println!("{}", error);
+            return
+        }
+    };
+
+    println!("loaded {} chain from: {}", params.network, data_dir.join(params.data_subdir).join("blocks").display());
+    println!("height:\t{}", chain.height());
+    println!("tip:\t{}", chain.tip().hash_hex());
+
+    let options = miner;
+    let miner = match options.threads {
+        Some(threads) if threads > 0 => rcoin::miner::new_with_threads(script_pub_key, options.tag.as_bytes(), threads),
+        Some(_) => {
+            println!("the miner needs at least one thread");
+            return
+        },
+        None => rcoin::miner::new(script_pub_key, options.tag.as_bytes()),
+    };
+
+    println!("starting miner with {} threads...", miner.threads());
+
+    // nothing relays transactions to the mempool yet, only the ones dumped by a previous run.
+    let mut mempool = rcoin::mempool::new();
+    let mempool_path = data_dir.join(params.data_subdir).join(rcoin::mempool::MEMPOOL_FILE);
+
+    match mempool.load(&mempool_path, chain.utxos(), chain.height() as u32 + 1) {
+        Ok(count) => println!("loaded {} mempool transactions from: {}", count, mempool_path.display()),
+        Err(error) => println!("could not load the mempool: {}", error),
+    };
+
+    let start = Instant::now();
+    let mut mined = 0;
+
+    while options.blocks.is_none_or(|blocks| mined < blocks) {
+        // the target is adjusted every 2016 blocks.
+        let mut context = chain.next_block_context();
+        if let Some(bits) = options.bits {
+            context.bits = bits;
+        };
+
+        let target = rcoin::difficulty::bits_to_target(context.bits);
+        println!();
+        println!("mining block {} at bits {:08x}, target {}", context.height, context.bits, hex::encode(target.to_be_bytes()));
+
+        let template = rcoin::blocktemplate::new(context, &mempool);
+
+        let block_start = Instant::now();
+        let hashes_before = miner.hashes();
+
+        // prints the status lines while the threads are mining.
+        let new_block = thread::scope(|scope| {
+            let worker = scope.spawn(|| miner.next(&template));
+            let mut last_status = Instant::now();
 
-    println!("current target difficulty:\t{}", target);
-    println!("current target difficulty hex:\t{}", hex::encode(target.to_be_bytes()));
-    println!("");
+            while !worker.is_finished() {
+                thread::sleep(Duration::from_millis(100));
 
-    let miner = rcoin::miner::new();
-    let mut previous_block = rcoin::block::genesis();
+                if last_status.elapsed() >= Duration::from_secs(options.status_interval) {
+                    print_status(miner.hashes() - hashes_before, block_start.elapsed(), template.context.bits);
+                    last_status = Instant::now();
+                };
+            };
 
-    println!("starting miner...");
+            worker.join().unwrap()
+        });
 
-    loop {
-        let new_block = miner.next(&previous_block, target_bits);
+        mined += 1;
+        println!(
+            "block {} found: {} after {} hashes in {:.1}s ({} blocks in {:.1}s)",
+            template.context.height, new_block.hash_hex(), miner.hashes() - hashes_before,
+            block_start.elapsed().as_secs_f64(), mined, start.elapsed().as_secs_f64(),
+        );
 
-        println!("");
-        println!("--- block found! ---");
-        println!("{}", rcoin::serializers::block_serializer::to_json_pretty(&new_block));
+        if options.json {
+            println!("{}", rcoin::serializers::block_serializer::to_json_pretty(&new_block));
+        }
 
-        previous_block = new_block;
+        if options.bits.is_some() {
+            continue
+        }
+
+        if let Err(error) = chain.add_block(new_block.clone()) {
+            println!("could not add block: {}", error);
+            return
+        }
+
+        mempool.remove_for_block(&new_block);
+
+        if let Err(error) = mempool.dump(&mempool_path) {
+            println!("could not dump the mempool: {}", error);
+        }
     }
 }
+
+/// Prints the hashrate since the start of the block, and how long a block is expected to take at
+/// that hashrate.
+fn print_status(hashes: u64, elapsed: Duration, bits: u32) {
+    let hashrate = hashes as f64 / elapsed.as_secs_f64();
+    let expected_hashes = rcoin::difficulty::work_from_bits(bits).as_f64();
+
+    println!(
+        "{:.0}s elapsed, {} nounces tried, {:.0} hashes/s, expected time to block: {:.0}s",
+        elapsed.as_secs_f64(), hashes, hashrate, expected_hashes / hashrate,
+    );
+}
+
+fn bench(duration: Duration) {
+    let full = rcoin::miner::hashrate(duration, false);
+    println!("full header:\t{:.0} hashes/s", full);
+
+    let midstate = rcoin::miner::hashrate(duration, true);
+    println!("midstate:\t{:.0} hashes/s", midstate);
+    println!("speedup:\t{:.2}x", midstate / full);
+}
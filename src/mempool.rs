@@ -0,0 +1,1200 @@
+use std::cmp::Ordering;
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::time::SystemTime;
+
+use crate::block::Block;
+use crate::encoding::{self, CompactSize, Decodable, Encodable};
+use crate::orphanage::{self, Orphanage};
+use crate::policy;
+use crate::script::engine;
+use crate::transactions::{OutPoint, Transaction};
+use crate::u256;
+use crate::utxo::UtxoSet;
+use crate::validation::{self, BlockError, COINBASE_MATURITY, MAX_MONEY};
+
+// The transactions waiting to be mined. A transaction can spend the outputs of the active chain,
+// or of other mempool transactions (its parents), but no output can be spent twice.
+//
+// Transactions must be standard (see `policy`) and pay the minimum relay fee. The scriptSig of
+// every input must unlock the output it spends, see `script::engine`. The engine can't check
+// signatures nor witnesses yet, so only outputs locked by its few opcodes can be spent, like the
+// P2SH outputs of the tests: every P2PK, P2PKH, multisig or segwit spend is rejected, the mempool
+// can't hold real transactions yet. Once the mempool is full, the transactions paying the lowest
+// fee rates are evicted, along with their descendants.
+//
+// A transaction spending the same output as mempool transactions replaces them if they signal
+// replaceability and it pays more, following BIP125:
+//
+// https://github.com/bitcoin/bips/blob/master/bip-0125.mediawiki
+//
+// A package, a child with its unconfirmed parents, is accepted if the package pays the minimum
+// relay fee, even if a parent doesn't: the child pays for its parents (CPFP).
+//
+// A transaction spending outputs of unknown transactions is kept in the orphan pool, and added
+// once its parents are.
+//
+// The mempool is dumped to mempool.dat, in the data directory, to survive restarts. Like bitcoin
+// core, the file holds:
+//
+//  version (u64) | count (u64) | count * (transaction | entry time (u64) | fee delta (i64))
+//  | the fee deltas of transactions not in the mempool, as a vector of (txid, fee delta)
+//
+// https://github.com/bitcoin/bitcoin/blob/master/src/node/mempool_persist.cpp
+
+/// Default maximum size of the mempool, in virtual bytes.
+pub const DEFAULT_MAX_SIZE: usize = 300_000_000;
+
+/// Maximum number of mempool ancestors of a transaction, itself included.
+pub const ANCESTOR_LIMIT: usize = 25;
+
+/// Maximum number of mempool descendants of a transaction, itself included.
+pub const DESCENDANT_LIMIT: usize = 25;
+
+/// Maximum number of transactions a replacement can evict, descendants included.
+pub const MAX_REPLACEMENT_CANDIDATES: usize = 100;
+
+pub const MEMPOOL_FILE: &str = "mempool.dat";
+
+const MEMPOOL_DUMP_VERSION: u64 = 1;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MempoolEntry {
+    pub tx: Transaction,
+    pub txid: u256,
+    /// The value of the inputs minus the value of the outputs.
+    pub fee: u64,
+    pub weight: usize,
+    /// When the transaction entered the mempool, in seconds since the unix epoch.
+    pub time: u64,
+    /// Added to the fee when selecting, evicting and replacing transactions, see `prioritise`.
+    pub fee_delta: i64,
+}
+
+pub struct Mempool {
+    entries: HashMap<u256, MempoolEntry>,
+    /// The mempool transaction spending each outpoint.
+    spenders: HashMap<OutPoint, u256>,
+    /// Total virtual size of the transactions.
+    size: usize,
+    max_size: usize,
+    /// The fee deltas set by `prioritise`, for transactions in the mempool or not.
+    deltas: HashMap<u256, i64>,
+    orphans: Orphanage,
+    /// The fees and weight of each transaction with its descendants, kept up to date as the
+    /// transactions come and go, so `trim_to_size` doesn't walk the whole mempool.
+    descendant_scores: HashMap<u256, DescendantScore>,
+    /// The same scores, the first one evicted first.
+    by_descendant_score: BTreeSet<DescendantScore>,
+}
+
+/// A transaction with its descendants, ordered by fee rate, then by txid.
+#[derive(Clone, Copy, Debug)]
+struct DescendantScore {
+    txid: u256,
+    fee: u64,
+    weight: usize,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum Error {
+    AlreadyInMempool,
+    /// Coinbases are only valid in blocks.
+    Coinbase,
+    Invalid(BlockError),
+    /// The output isn't in the UTXO set nor created by a mempool transaction.
+    MissingInput(OutPoint),
+    /// The output is already spent by another mempool transaction, which can't be replaced.
+    Conflict(OutPoint),
+    /// The transaction doesn't follow the replacement rules, for the given reason.
+    Replacement(&'static str),
+    /// The package isn't a child with its parents, for the given reason.
+    Package(&'static str),
+    ImmatureCoinbaseSpend(OutPoint),
+    InputsBelowOutputs,
+    /// The script of an input of the transaction failed, or couldn't be verified.
+    ScriptFailure(u256),
+    /// The transaction breaks the standardness policy, for the given reason.
+    NonStandard(&'static str),
+    MinRelayFee { fee: u64, min: u64 },
+    /// Too many ancestors or descendants in the mempool.
+    TooLongMempoolChain,
+    /// The mempool is full of transactions paying better fee rates.
+    MempoolFull,
+}
+
+/// Why mempool.dat couldn't be written or read.
+#[derive(Debug)]
+pub enum DumpError {
+    Io(io::Error),
+    Encoding(encoding::Error),
+    UnknownVersion(u64),
+}
+
+pub fn new() -> Mempool {
+    new_with_max_size(DEFAULT_MAX_SIZE)
+}
+
+/// A mempool holding at most `max_size` virtual bytes of transactions.
+pub fn new_with_max_size(max_size: usize) -> Mempool {
+    Mempool {
+        entries: HashMap::new(),
+        spenders: HashMap::new(),
+        size: 0,
+        max_size,
+        deltas: HashMap::new(),
+        orphans: orphanage::new(),
+        descendant_scores: HashMap::new(),
+        by_descendant_score: BTreeSet::new(),
+    }
+}
+
+impl Mempool {
+    pub fn get(&self, txid: &u256) -> Option<&MempoolEntry> {
+        self.entries.get(txid)
+    }
+
+    pub fn contains(&self, txid: &u256) -> bool {
+        self.entries.contains_key(txid)
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Total virtual size of the transactions.
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    pub fn entries(&self) -> impl Iterator<Item = &MempoolEntry> {
+        self.entries.values()
+    }
+
+    /// The transactions waiting for their parents.
+    pub fn orphans(&self) -> &Orphanage {
+        &self.orphans
+    }
+
+    /// The mempool transaction spending `outpoint`, if any.
+    pub fn spender(&self, outpoint: &OutPoint) -> Option<&MempoolEntry> {
+        self.spenders.get(outpoint).map(|txid| &self.entries[txid])
+    }
+
+    /// Adds a transaction spending outputs of `utxos` or of the mempool, to be mined at `height`.
+    /// It replaces the mempool transactions it conflicts with, if it can. Returns its txid.
+    ///
+    /// A transaction spending outputs of unknown transactions fails with `MissingInput`, but is
+    /// kept as an orphan. The orphans spending its outputs are added along with it.
+    pub fn add(&mut self, tx: Transaction, utxos: &UtxoSet, height: u32) -> Result<u256, Error> {
+        let txid = match self.accept(&tx, utxos, height, false) {
+            Ok(txid) => txid,
+            Err(Error::MissingInput(outpoint)) => {
+                if self.has_unknown_parent(&tx, utxos) {
+                    self.orphans.add(tx, current_time());
+                };
+                return Err(Error::MissingInput(outpoint))
+            },
+            Err(error) => return Err(error),
+        };
+
+        self.add_orphans(txid, utxos, height);
+        self.trim_to_size();
+
+        if !self.contains(&txid) {
+            return Err(Error::MempoolFull)
+        };
+
+        Ok(txid)
+    }
+
+    /// Adds a child and its parents, sorted so that parents come first. Parents already in the
+    /// mempool are skipped. The others, with the child, must pay the minimum relay fee together.
+    /// Returns the txids of the added transactions.
+    pub fn add_package(&mut self, package: &[Transaction], utxos: &UtxoSet, height: u32) -> Result<Vec<u256>, Error> {
+        check_package(package)?;
+
+        let mut added = vec![];
+        for tx in package {
+            if self.contains(&tx.txid()) {
+                continue
+            };
+
+            match self.accept(tx, utxos, height, true) {
+                Ok(txid) => added.push(txid),
+                Err(error) => {
+                    added.iter().for_each(|txid| self.remove_with_descendants(txid));
+                    return Err(error)
+                },
+            };
+        };
+
+        let fee: u64 = added.iter().map(|txid| self.entries[txid].modified_fee()).sum();
+        let vsize: usize = added.iter().map(|txid| self.entries[txid].weight.div_ceil(4)).sum();
+        let min = policy::fee_for_size(vsize, policy::MIN_RELAY_FEE_RATE);
+
+        if fee < min {
+            added.iter().for_each(|txid| self.remove_with_descendants(txid));
+            return Err(Error::MinRelayFee { fee, min })
+        };
+
+        for txid in &added {
+            self.add_orphans(*txid, utxos, height);
+        };
+        self.trim_to_size();
+
+        if added.iter().any(|txid| !self.contains(txid)) {
+            return Err(Error::MempoolFull)
+        };
+
+        Ok(added)
+    }
+
+    /// Whether the transaction, or one of its mempool ancestors, signals replaceability.
+    pub fn is_replaceable(&self, txid: &u256) -> bool {
+        let mut txids = self.ancestors(txid);
+        txids.insert(*txid);
+
+        txids.iter().any(|txid| self.entries[txid].tx.signals_rbf())
+    }
+
+    /// Adds `delta` satoshis to the fee of the transaction when checking the minimum relay fee,
+    /// selecting transactions for a block, evicting and replacing them. The transaction doesn't
+    /// have to be in the mempool yet. Like bitcoin core's prioritisetransaction.
+    pub fn prioritise(&mut self, txid: u256, delta: i64) {
+        let fee_delta = self.fee_delta(&txid) + delta;
+
+        if fee_delta == 0 {
+            self.deltas.remove(&txid);
+        } else {
+            self.deltas.insert(txid, fee_delta);
+        };
+
+        if let Some(entry) = self.entries.get_mut(&txid) {
+            entry.fee_delta = fee_delta;
+
+            let mut changed = self.ancestors(&txid);
+            changed.insert(txid);
+            self.update_descendant_scores(changed);
+        };
+    }
+
+    pub fn fee_delta(&self, txid: &u256) -> i64 {
+        self.deltas.get(txid).copied().unwrap_or(0)
+    }
+
+    /// Checks and inserts the transaction, evicting the transactions it replaces. A transaction of
+    /// a package doesn't need to pay the minimum relay fee on its own, and can't replace others.
+    fn accept(&mut self, tx: &Transaction, utxos: &UtxoSet, height: u32, in_package: bool) -> Result<u256, Error> {
+        let txid = tx.txid();
+
+        if self.contains(&txid) {
+            return Err(Error::AlreadyInMempool)
+        };
+
+        if tx.is_coinbase() {
+            return Err(Error::Coinbase)
+        };
+
+        validation::check_transaction(tx).map_err(Error::Invalid)?;
+        policy::check_standard(tx).map_err(Error::NonStandard)?;
+
+        let fee = self.fee(tx, utxos, height)?;
+        let fee_delta = self.fee_delta(&txid);
+        let modified_fee = fee.saturating_add_signed(fee_delta);
+
+        let min = policy::fee_for_size(tx.vsize(), policy::MIN_RELAY_FEE_RATE);
+        if modified_fee < min && !in_package {
+            return Err(Error::MinRelayFee { fee: modified_fee, min })
+        };
+
+        let conflicts: HashSet<u256> = tx.inputs.iter()
+            .filter_map(|txin| self.spenders.get(&txin.outpoint()))
+            .copied()
+            .collect();
+
+        let replaced = if conflicts.is_empty() {
+            HashSet::new()
+        } else if in_package {
+            let outpoint = tx.inputs.iter().map(|txin| txin.outpoint()).find(|outpoint| self.spenders.contains_key(outpoint));
+            return Err(Error::Conflict(outpoint.unwrap()))
+        } else {
+            self.check_replacement(tx, modified_fee, &conflicts)?
+        };
+
+        self.check_chain_limits(tx)?;
+        self.check_scripts(tx, utxos)?;
+
+        for txid in &replaced {
+            self.remove(txid);
+        };
+        self.insert(MempoolEntry { txid, fee, weight: tx.weight(), time: current_time(), fee_delta, tx: tx.clone() });
+
+        Ok(txid)
+    }
+
+    /// The mempool transactions whose outputs `txid` spends.
+    pub fn parents(&self, txid: &u256) -> HashSet<u256> {
+        self.entries[txid].tx.inputs.iter()
+            .map(|txin| txin.txid)
+            .filter(|parent| self.contains(parent))
+            .collect()
+    }
+
+    /// The mempool transactions spending the outputs of `txid`.
+    pub fn children(&self, txid: &u256) -> HashSet<u256> {
+        (0..self.entries[txid].tx.outputs.len())
+            .filter_map(|vout| self.spenders.get(&OutPoint { txid: *txid, vout: vout as u32 }))
+            .copied()
+            .collect()
+    }
+
+    /// The parents of `txid`, their parents, and so on.
+    pub fn ancestors(&self, txid: &u256) -> HashSet<u256> {
+        self.walk(txid, |txid| self.parents(txid))
+    }
+
+    /// The children of `txid`, their children, and so on.
+    pub fn descendants(&self, txid: &u256) -> HashSet<u256> {
+        self.walk(txid, |txid| self.children(txid))
+    }
+
+    /// Removes the transactions mined in the block, and the ones conflicting with them.
+    pub fn remove_for_block(&mut self, block: &Block) {
+        for tx in block.transactions.iter().filter(|tx| !tx.is_coinbase()) {
+            // its children stay, now spending an output of the chain.
+            self.remove(&tx.txid());
+            self.deltas.remove(&tx.txid());
+
+            for txin in &tx.inputs {
+                if let Some(conflict) = self.spenders.get(&txin.outpoint()).copied() {
+                    self.remove_with_descendants(&conflict);
+                };
+            };
+        };
+
+        self.orphans.remove_for_block(block);
+    }
+
+    /// Removes the transaction, and every transaction spending its outputs.
+    pub fn remove_with_descendants(&mut self, txid: &u256) {
+        if !self.contains(txid) {
+            return
+        };
+
+        let mut removed = self.descendants(txid);
+        removed.insert(*txid);
+
+        for txid in removed {
+            self.remove(&txid);
+        };
+    }
+
+    /// Evicts the transactions with the lowest descendant fee rate, and their descendants, until
+    /// the mempool isn't larger than its maximum size. A parent paid for by its children stays.
+    pub fn trim_to_size(&mut self) {
+        while self.size > self.max_size {
+            let worst = self.by_descendant_score.first().unwrap().txid;
+            self.remove_with_descendants(&worst);
+        };
+    }
+
+    fn insert(&mut self, entry: MempoolEntry) {
+        let txid = entry.txid;
+
+        for txin in &entry.tx.inputs {
+            self.spenders.insert(txin.outpoint(), txid);
+        };
+
+        self.size += entry.weight.div_ceil(4);
+        self.entries.insert(txid, entry);
+
+        let mut changed = self.ancestors(&txid);
+        changed.insert(txid);
+        self.update_descendant_scores(changed);
+    }
+
+    fn remove(&mut self, txid: &u256) -> Option<MempoolEntry> {
+        if !self.contains(txid) {
+            return None
+        };
+
+        let mut changed = self.ancestors(txid);
+        let entry = self.entries.remove(txid).unwrap();
+
+        for txin in &entry.tx.inputs {
+            self.spenders.remove(&txin.outpoint());
+        };
+
+        self.size -= entry.weight.div_ceil(4);
+
+        changed.insert(*txid);
+        self.update_descendant_scores(changed);
+
+        Some(entry)
+    }
+
+    /// Recomputes the descendant scores of the transactions, dropping the ones no longer in the
+    /// mempool. A transaction changes the scores of its ancestors, which the chain limits bound.
+    fn update_descendant_scores(&mut self, txids: HashSet<u256>) {
+        for txid in txids {
+            if let Some(score) = self.descendant_scores.remove(&txid) {
+                self.by_descendant_score.remove(&score);
+            };
+
+            if self.contains(&txid) {
+                let (fee, weight) = self.descendants_fee_and_weight(&txid);
+                let score = DescendantScore { txid, fee, weight };
+
+                self.descendant_scores.insert(txid, score);
+                self.by_descendant_score.insert(score);
+            };
+        };
+    }
+
+    /// The fee and weight of the transaction and its descendants.
+    fn descendants_fee_and_weight(&self, txid: &u256) -> (u64, usize) {
+        let mut txids = self.descendants(txid);
+        txids.insert(*txid);
+
+        txids.iter()
+            .map(|txid| &self.entries[txid])
+            .fold((0, 0), |(fee, weight), entry| (fee + entry.modified_fee(), weight + entry.weight))
+    }
+
+    /// Whether the transaction spends an output of a transaction that is neither in the mempool nor
+    /// unspent in `utxos`, as far as we know. Its parent could also be spent already.
+    fn has_unknown_parent(&self, tx: &Transaction, utxos: &UtxoSet) -> bool {
+        tx.inputs.iter().any(|txin| !utxos.contains(&txin.outpoint()) && !self.contains(&txin.txid))
+    }
+
+    /// Adds the orphans spending the outputs of `txid`, then the orphans of the ones added, and so
+    /// on. An orphan still missing other parents stays in the pool, the invalid ones are dropped.
+    fn add_orphans(&mut self, txid: u256, utxos: &UtxoSet, height: u32) {
+        let mut queue = vec![txid];
+
+        while let Some(parent) = queue.pop() {
+            // a replacement could evict its parent.
+            let children = match self.entries.get(&parent) {
+                Some(entry) => self.orphans.children(&entry.tx),
+                None => continue,
+            };
+
+            for child in children {
+                let tx = match self.orphans.get(&child) {
+                    Some(orphan) => orphan.tx.clone(),
+                    None => continue,
+                };
+
+                match self.accept(&tx, utxos, height, false) {
+                    Ok(txid) => {
+                        self.orphans.remove(&txid);
+                        queue.push(txid);
+                    },
+                    Err(Error::MissingInput(_)) if self.has_unknown_parent(&tx, utxos) => {},
+                    Err(_) => {
+                        self.orphans.remove(&child);
+                    },
+                };
+            };
+        };
+    }
+
+    /// Checks that the new transaction doesn't have too many ancestors, and doesn't give too many
+    /// descendants to one of them.
+    fn check_chain_limits(&self, tx: &Transaction) -> Result<(), Error> {
+        let mut ancestors: HashSet<u256> = HashSet::new();
+
+        for parent in tx.inputs.iter().map(|txin| txin.txid).filter(|txid| self.contains(txid)) {
+            if ancestors.insert(parent) {
+                ancestors.extend(self.ancestors(&parent));
+            };
+        };
+
+        if ancestors.len() + 1 > ANCESTOR_LIMIT {
+            return Err(Error::TooLongMempoolChain)
+        };
+
+        // the transaction itself, its descendants, and the new one.
+        if ancestors.iter().any(|ancestor| self.descendants(ancestor).len() + 2 > DESCENDANT_LIMIT) {
+            return Err(Error::TooLongMempoolChain)
+        };
+
+        Ok(())
+    }
+
+    /// Runs the scriptSig of every input, then the scriptPubKey of the output it spends, which
+    /// must be in the mempool or in `utxos`.
+    fn check_scripts(&self, tx: &Transaction, utxos: &UtxoSet) -> Result<(), Error> {
+        for txin in &tx.inputs {
+            let outpoint = txin.outpoint();
+
+            let script_pub_key = match (self.entries.get(&outpoint.txid), utxos.get(&outpoint)) {
+                (Some(parent), _) => &parent.tx.outputs[outpoint.vout as usize].script_pub_key,
+                (None, Some(coin)) => &coin.output.script_pub_key,
+                (None, None) => return Err(Error::MissingInput(outpoint)),
+            };
+
+            if engine::new().verify(&txin.script_sig, script_pub_key) != Ok(true) {
+                return Err(Error::ScriptFailure(tx.txid()))
+            };
+        };
+
+        Ok(())
+    }
+
+    /// Checks the inputs of the transaction, and returns its fee.
+    fn fee(&self, tx: &Transaction, utxos: &UtxoSet, height: u32) -> Result<u64, Error> {
+        let mut spent = HashSet::new();
+        let mut value_in: u64 = 0;
+
+        for txin in &tx.inputs {
+            let outpoint = txin.outpoint();
+
+            if !spent.insert(outpoint) {
+                return Err(Error::Invalid(BlockError::DoubleSpend(outpoint)))
+            };
+
+            let value = match (self.entries.get(&outpoint.txid), utxos.get(&outpoint)) {
+                (Some(parent), _) => match parent.tx.outputs.get(outpoint.vout as usize) {
+                    Some(output) => output.value,
+                    None => return Err(Error::MissingInput(outpoint)),
+                },
+                (None, Some(coin)) => {
+                    if coin.is_coinbase && height.saturating_sub(coin.height) < COINBASE_MATURITY {
+                        return Err(Error::ImmatureCoinbaseSpend(outpoint))
+                    };
+                    coin.output.value
+                },
+                (None, None) => return Err(Error::MissingInput(outpoint)),
+            };
+
+            value_in += value;
+            if value_in > MAX_MONEY {
+                return Err(Error::Invalid(BlockError::ValueOutOfRange(tx.txid())))
+            };
+        };
+
+        let value_out: u64 = tx.outputs.iter().map(|output| output.value).sum();
+
+        value_in.checked_sub(value_out).ok_or(Error::InputsBelowOutputs)
+    }
+
+    /// Checks the BIP125 rules for `tx`, paying the modified `fee`, to replace the mempool
+    /// transactions it conflicts with. Returns every transaction to evict: the conflicts and their
+    /// descendants.
+    fn check_replacement(&self, tx: &Transaction, fee: u64, conflicts: &HashSet<u256>) -> Result<HashSet<u256>, Error> {
+        for conflict in conflicts {
+            if !self.is_replaceable(conflict) {
+                let outpoint = tx.inputs.iter()
+                    .map(|txin| txin.outpoint())
+                    .find(|outpoint| self.spenders.get(outpoint) == Some(conflict));
+                return Err(Error::Conflict(outpoint.unwrap()))
+            };
+        };
+
+        let mut replaced = conflicts.clone();
+        for conflict in conflicts {
+            replaced.extend(self.descendants(conflict));
+        };
+
+        if replaced.len() > MAX_REPLACEMENT_CANDIDATES {
+            return Err(Error::Replacement("too many potential replacements"))
+        };
+
+        // the replacement can't spend what it replaces, nor new unconfirmed outputs.
+        let original_parents: HashSet<u256> = conflicts.iter().flat_map(|conflict| self.parents(conflict)).collect();
+
+        for parent in tx.inputs.iter().map(|txin| txin.txid).filter(|txid| self.contains(txid)) {
+            if replaced.contains(&parent) || self.ancestors(&parent).iter().any(|ancestor| replaced.contains(ancestor)) {
+                return Err(Error::Replacement("spends conflicting transaction"))
+            };
+
+            if !original_parents.contains(&parent) {
+                return Err(Error::Replacement("replacement-adds-unconfirmed"))
+            };
+        };
+
+        // a higher fee rate than each transaction it directly replaces.
+        let weight = tx.weight();
+        for conflict in conflicts.iter().map(|txid| &self.entries[txid]) {
+            if fee as u128 * conflict.weight as u128 <= conflict.modified_fee() as u128 * weight as u128 {
+                return Err(Error::Replacement("insufficient fee rate"))
+            };
+        };
+
+        // more fees than everything it evicts, plus the relay of its own size.
+        let replaced_fees: u64 = replaced.iter().map(|txid| self.entries[txid].modified_fee()).sum();
+        if fee < replaced_fees + policy::fee_for_size(tx.vsize(), policy::INCREMENTAL_RELAY_FEE_RATE) {
+            return Err(Error::Replacement("insufficient fee"))
+        };
+
+        Ok(replaced)
+    }
+
+    /// Writes the transactions and the fee deltas to `path`, parents first. The file is replaced
+    /// at once, so a crash can't leave it half written.
+    pub fn dump(&self, path: &Path) -> Result<(), DumpError> {
+        let mut entries: Vec<&MempoolEntry> = self.entries.values().collect();
+        entries.sort_by_key(|entry| (self.ancestors(&entry.txid).len(), entry.txid));
+
+        let mut bytes = vec![];
+        MEMPOOL_DUMP_VERSION.consensus_encode(&mut bytes)?;
+        (entries.len() as u64).consensus_encode(&mut bytes)?;
+
+        for entry in entries {
+            entry.tx.consensus_encode(&mut bytes)?;
+            entry.time.consensus_encode(&mut bytes)?;
+            entry.fee_delta.consensus_encode(&mut bytes)?;
+        };
+
+        let mut deltas: Vec<(u256, i64)> = self.deltas.iter()
+            .filter(|(txid, _)| !self.contains(txid))
+            .map(|(txid, delta)| (*txid, *delta))
+            .collect();
+        deltas.sort();
+
+        CompactSize(deltas.len() as u64).consensus_encode(&mut bytes)?;
+        for delta in &deltas {
+            delta.consensus_encode(&mut bytes)?;
+        };
+
+        let temp_path = path.with_extension("dat.new");
+        fs::write(&temp_path, bytes)?;
+        fs::rename(temp_path, path)?;
+        Ok(())
+    }
+
+    /// Adds the transactions dumped to `path`, with their entry time and fee delta. They are
+    /// checked again against `utxos` and `height`, and the ones now invalid, e.g. mined or double
+    /// spent while the node was down, are dropped but keep their delta. Returns the number of
+    /// transactions added. A missing file adds nothing.
+    pub fn load(&mut self, path: &Path, utxos: &UtxoSet, height: u32) -> Result<usize, DumpError> {
+        let bytes = match fs::read(path) {
+            Ok(bytes) => bytes,
+            Err(error) if error.kind() == io::ErrorKind::NotFound => return Ok(0),
+            Err(error) => return Err(error.into()),
+        };
+
+        let mut reader = &bytes[..];
+        let version = u64::consensus_decode(&mut reader)?;
+        if version != MEMPOOL_DUMP_VERSION {
+            return Err(DumpError::UnknownVersion(version))
+        };
+
+        // the whole file is read first, a corrupted file adds nothing.
+        let count = u64::consensus_decode(&mut reader)?;
+        let mut entries = vec![];
+        for _ in 0..count {
+            let tx = Transaction::consensus_decode(&mut reader)?;
+            let time = u64::consensus_decode(&mut reader)?;
+            let fee_delta = i64::consensus_decode(&mut reader)?;
+            entries.push((tx, time, fee_delta));
+        };
+
+        let mut deltas = vec![];
+        for _ in 0..encoding::decode_length(&mut reader)? {
+            deltas.push(<(u256, i64)>::consensus_decode(&mut reader)?);
+        };
+
+        for (txid, delta) in deltas {
+            self.prioritise(txid, delta);
+        };
+
+        let mut added = vec![];
+        for (tx, time, fee_delta) in entries {
+            self.prioritise(tx.txid(), fee_delta);
+
+            if let Ok(txid) = self.accept(&tx, utxos, height, false) {
+                self.entries.get_mut(&txid).unwrap().time = time;
+                added.push(txid);
+            };
+        };
+
+        self.trim_to_size();
+
+        Ok(added.iter().filter(|txid| self.contains(txid)).count())
+    }
+
+    fn walk(&self, txid: &u256, next: impl Fn(&u256) -> HashSet<u256>) -> HashSet<u256> {
+        let mut found = HashSet::new();
+        let mut queue = vec![*txid];
+
+        while let Some(txid) = queue.pop() {
+            for other in next(&txid) {
+                if found.insert(other) {
+                    queue.push(other);
+                };
+            };
+        };
+
+        found
+    }
+}
+
+impl MempoolEntry {
+    /// The fee with the delta set by `prioritise`.
+    pub fn modified_fee(&self) -> u64 {
+        self.fee.saturating_add_signed(self.fee_delta)
+    }
+}
+
+impl Ord for DescendantScore {
+    /// Compares fee / weight without rounding.
+    fn cmp(&self, other: &DescendantScore) -> Ordering {
+        (self.fee as u128 * other.weight as u128).cmp(&(other.fee as u128 * self.weight as u128))
+            .then(self.txid.cmp(&other.txid))
+    }
+}
+
+impl PartialOrd for DescendantScore {
+    fn partial_cmp(&self, other: &DescendantScore) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for DescendantScore {
+    fn eq(&self, other: &DescendantScore) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for DescendantScore {}
+
+/// Checks that the package is a child with its parents, sorted, and not too large.
+fn check_package(package: &[Transaction]) -> Result<(), Error> {
+    if package.is_empty() || package.len() > policy::MAX_PACKAGE_COUNT {
+        return Err(Error::Package("package-too-many-transactions"))
+    };
+
+    if package.iter().map(|tx| tx.weight()).sum::<usize>() > policy::MAX_PACKAGE_WEIGHT {
+        return Err(Error::Package("package-too-large"))
+    };
+
+    let txids: Vec<u256> = package.iter().map(|tx| tx.txid()).collect();
+    let (child, parents) = package.split_last().unwrap();
+
+    if parents.iter().enumerate().any(|(i, parent)| parent.inputs.iter().any(|txin| txids[i..].contains(&txin.txid))) {
+        return Err(Error::Package("package-not-sorted"))
+    };
+
+    let spent: HashSet<u256> = child.inputs.iter().map(|txin| txin.txid).collect();
+    if !txids[..parents.len()].iter().all(|txid| spent.contains(txid)) {
+        return Err(Error::Package("package-not-child-with-parents"))
+    };
+
+    Ok(())
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let outpoint = |outpoint: &OutPoint| format!("{}:{}", hex::encode(outpoint.txid.to_be_bytes()), outpoint.vout);
+
+        match self {
+            Error::AlreadyInMempool => write!(f, "transaction already in the mempool"),
+            Error::Coinbase => write!(f, "coinbase transaction outside of a block"),
+            Error::Invalid(error) => write!(f, "invalid transaction: {}", error),
+            Error::MissingInput(spent) => write!(f, "missing or spent output {}", outpoint(spent)),
+            Error::Conflict(spent) => write!(f, "output {} already spent in the mempool", outpoint(spent)),
+            Error::Replacement(reason) => write!(f, "can't replace the conflicting transactions: {}", reason),
+            Error::Package(reason) => write!(f, "invalid package: {}", reason),
+            Error::ImmatureCoinbaseSpend(spent) => write!(f, "immature coinbase output {} spent", outpoint(spent)),
+            Error::InputsBelowOutputs => write!(f, "transaction spends more than its inputs"),
+            Error::ScriptFailure(txid) => write!(f, "script verification failed in transaction {}", hex::encode(txid.to_be_bytes())),
+            Error::NonStandard(reason) => write!(f, "non standard transaction: {}", reason),
+            Error::MinRelayFee { fee, min } => write!(f, "fee of {} below the minimum relay fee of {}", fee, min),
+            Error::TooLongMempoolChain => write!(f, "too many unconfirmed ancestors or descendants"),
+            Error::MempoolFull => write!(f, "mempool full"),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl fmt::Display for DumpError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DumpError::Io(error) => write!(f, "{}", error),
+            DumpError::Encoding(error) => write!(f, "invalid {}: {}", MEMPOOL_FILE, error),
+            DumpError::UnknownVersion(version) => write!(f, "unknown {} version {}", MEMPOOL_FILE, version),
+        }
+    }
+}
+
+impl std::error::Error for DumpError {}
+
+impl From<io::Error> for DumpError {
+    fn from(error: io::Error) -> DumpError {
+        DumpError::Io(error)
+    }
+}
+
+impl From<encoding::Error> for DumpError {
+    fn from(error: encoding::Error) -> DumpError {
+        DumpError::Encoding(error)
+    }
+}
+
+fn current_time() -> u64 {
+    SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+}
+
+#[cfg(test)]
+pub(crate) mod test {
+    use super::*;
+    use crate::block;
+    use crate::script::Script;
+    use crate::transactions::MAX_BIP125_RBF_SEQUENCE;
+    use crate::transactions::test::{coinbase, outpoint, spend};
+    use crate::utxo;
+    use crate::validation::COIN;
+
+    /// A UTXO set with `count` mature coinbase outputs of 50 btc, and the height to spend them at.
+    pub(crate) fn funded_utxos(count: usize) -> (UtxoSet, Vec<OutPoint>, u32) {
+        let mut utxos = utxo::new();
+        let coinbases: Vec<_> = (0..count).map(|i| coinbase(&(i as u32).to_le_bytes())).collect();
+
+        for (i, coinbase) in coinbases.iter().enumerate() {
+            let block = Block { header: block::genesis().header, transactions: vec![coinbase.clone()] };
+            utxos.connect_block(&block, i as u32 + 1).unwrap();
+        };
+
+        let outpoints = coinbases.iter().map(|coinbase| outpoint(coinbase, 0)).collect();
+        (utxos, outpoints, count as u32 + COINBASE_MATURITY)
+    }
+
+    #[test]
+    fn add_test() {
+        let (utxos, funding, height) = funded_utxos(1);
+        let mut mempool = new();
+
+        let parent = spend(&funding, &[20 * COIN, 29 * COIN]);
+        let child = spend(&[outpoint(&parent, 1)], &[28 * COIN]);
+        let grandchild = spend(&[outpoint(&child, 0)], &[27 * COIN]);
+
+        assert_eq!(Ok(parent.txid()), mempool.add(parent.clone(), &utxos, height));
+        assert_eq!(Ok(child.txid()), mempool.add(child.clone(), &utxos, height));
+        assert_eq!(Ok(grandchild.txid()), mempool.add(grandchild.clone(), &utxos, height));
+
+        assert_eq!(COIN, mempool.get(&parent.txid()).unwrap().fee);
+        assert_eq!(HashSet::from([parent.txid()]), mempool.parents(&child.txid()));
+        assert_eq!(HashSet::from([parent.txid(), child.txid()]), mempool.ancestors(&grandchild.txid()));
+        assert_eq!(HashSet::from([child.txid(), grandchild.txid()]), mempool.descendants(&parent.txid()));
+    }
+
+    #[test]
+    fn add_errors_test() {
+        let (utxos, funding, height) = funded_utxos(1);
+        let mut mempool = new();
+
+        let tx = spend(&funding, &[49 * COIN]);
+        mempool.add(tx.clone(), &utxos, height).unwrap();
+
+        assert_eq!(Err(Error::AlreadyInMempool), mempool.add(tx.clone(), &utxos, height));
+        assert_eq!(Err(Error::Coinbase), mempool.add(coinbase(b"1"), &utxos, height));
+        assert_eq!(Err(Error::Conflict(funding[0])), mempool.add(spend(&funding, &[48 * COIN]), &utxos, height));
+        assert_eq!(Err(Error::MissingInput(outpoint(&tx, 1))), mempool.add(spend(&[outpoint(&tx, 1)], &[1000]), &utxos, height));
+        assert_eq!(Err(Error::InputsBelowOutputs), mempool.add(spend(&[outpoint(&tx, 0)], &[50 * COIN]), &utxos, height));
+
+        let mut mempool = new();
+        assert_eq!(Err(Error::ImmatureCoinbaseSpend(funding[0])), mempool.add(tx, &utxos, height - 1));
+    }
+
+    #[test]
+    fn policy_test() {
+        let (utxos, funding, height) = funded_utxos(1);
+        let mut mempool = new();
+
+        let mut tx = spend(&funding, &[49 * COIN]);
+        tx.outputs[0].script_pub_key = Script::from(vec![0x51; 22]);
+        assert_eq!(Err(Error::NonStandard("scriptpubkey")), mempool.add(tx, &utxos, height));
+
+        assert_eq!(Err(Error::NonStandard("dust")), mempool.add(spend(&funding, &[49 * COIN, 293]), &utxos, height));
+
+        // 1 satoshi per virtual byte.
+        let tx = spend(&funding, &[50 * COIN - 84]);
+        assert_eq!(85, tx.vsize());
+        assert_eq!(Err(Error::MinRelayFee { fee: 84, min: 85 }), mempool.add(tx, &utxos, height));
+        assert!(mempool.add(spend(&funding, &[50 * COIN - 85]), &utxos, height).is_ok());
+    }
+
+    #[test]
+    fn script_failure_test() {
+        let (utxos, funding, height) = funded_utxos(2);
+        let mut mempool = new();
+
+        // the pushed redeem script doesn't match the hash of the output.
+        let mut tx = spend(&funding[..1], &[49 * COIN]);
+        tx.inputs[0].script_sig = Script::from_hex("0160").unwrap();
+        assert_eq!(Err(Error::ScriptFailure(tx.txid())), mempool.add(tx.clone(), &utxos, height));
+
+        let parent = spend(&funding[..1], &[49 * COIN]);
+        let mut child = spend(&[outpoint(&parent, 0)], &[48 * COIN]);
+        child.inputs[0].script_sig = Script::from_hex("0160").unwrap();
+        assert_eq!(Err(Error::ScriptFailure(child.txid())), mempool.add_package(&[parent, child], &utxos, height));
+        assert!(mempool.is_empty());
+
+        // the engine can't verify the spend of a witness program.
+        let mut parent = spend(&funding[1..], &[49 * COIN]);
+        parent.outputs[0].script_pub_key = Script::new_witness_program(0, &[0xab; 20]);
+        let mut child = spend(&[outpoint(&parent, 0)], &[48 * COIN]);
+        child.inputs[0].script_sig = Script::new();
+        child.inputs[0].witness = vec![vec![0xab; 72], vec![0x02; 33]];
+
+        mempool.add(parent, &utxos, height).unwrap();
+        assert_eq!(Err(Error::ScriptFailure(child.txid())), mempool.add(child, &utxos, height));
+    }
+
+    #[test]
+    fn chain_limits_test() {
+        let (utxos, funding, height) = funded_utxos(1);
+        let mut mempool = new();
+
+        let mut tx = spend(&funding, &[49 * COIN]);
+        for _ in 0..ANCESTOR_LIMIT {
+            mempool.add(tx.clone(), &utxos, height).unwrap();
+            tx = spend(&[outpoint(&tx, 0)], &[tx.outputs[0].value - 1000]);
+        };
+
+        assert_eq!(Err(Error::TooLongMempoolChain), mempool.add(tx, &utxos, height));
+    }
+
+    #[test]
+    fn trim_to_size_test() {
+        let (utxos, funding, height) = funded_utxos(3);
+
+        // room for two transactions.
+        let mut mempool = new_with_max_size(2 * 85);
+
+        let low = spend(&funding[..1], &[50 * COIN - 1000]);
+        let high = spend(&funding[1..2], &[50 * COIN - 3000]);
+        mempool.add(low.clone(), &utxos, height).unwrap();
+        mempool.add(high.clone(), &utxos, height).unwrap();
+
+        // the lowest fee rate is evicted, the new transaction or not.
+        let middle = spend(&funding[2..], &[50 * COIN - 2000]);
+        assert_eq!(Ok(middle.txid()), mempool.add(middle.clone(), &utxos, height));
+        assert!(!mempool.contains(&low.txid()));
+
+        let lowest = spend(&funding[..1], &[50 * COIN - 500]);
+        assert_eq!(Err(Error::MempoolFull), mempool.add(lowest, &utxos, height));
+        assert_eq!(2, mempool.len());
+        assert_eq!(2 * 85, mempool.size());
+    }
+
+    #[test]
+    fn descendant_score_test() {
+        let (utxos, funding, height) = funded_utxos(4);
+        let mut mempool = new_with_max_size(3 * 85);
+
+        // the child pays for its parent, the other transaction pays the lowest rate with its
+        // descendants.
+        let parent = spend(&funding[..1], &[50 * COIN - 100]);
+        let child = spend(&[outpoint(&parent, 0)], &[50 * COIN - 5100]);
+        let other = spend(&funding[1..2], &[50 * COIN - 1000]);
+        for tx in [&parent, &child, &other] {
+            mempool.add(tx.clone(), &utxos, height).unwrap();
+        };
+
+        let middle = spend(&funding[2..3], &[50 * COIN - 2000]);
+        mempool.add(middle.clone(), &utxos, height).unwrap();
+        assert!(!mempool.contains(&other.txid()));
+        assert!(mempool.contains(&parent.txid()));
+
+        // the child no longer pays anything, it goes first.
+        mempool.prioritise(child.txid(), -5000);
+        mempool.add(spend(&funding[3..], &[50 * COIN - 1500]), &utxos, height).unwrap();
+        assert!(!mempool.contains(&child.txid()));
+        assert!(mempool.contains(&parent.txid()));
+        assert_eq!(3, mempool.by_descendant_score.len());
+    }
+
+    fn signaling(mut tx: Transaction) -> Transaction {
+        tx.inputs[0].sequence = MAX_BIP125_RBF_SEQUENCE;
+        tx
+    }
+
+    #[test]
+    fn replacement_test() {
+        let (utxos, funding, height) = funded_utxos(2);
+        let mut mempool = new();
+
+        let original = signaling(spend(&funding[..1], &[49 * COIN]));
+        let child = spend(&[outpoint(&original, 0)], &[49 * COIN - 1000]);
+        mempool.add(original.clone(), &utxos, height).unwrap();
+        mempool.add(child.clone(), &utxos, height).unwrap();
+
+        // the child inherits the signal of its parent.
+        assert!(mempool.is_replaceable(&child.txid()));
+
+        // it must pay for the evicted fees, 1 btc + 1000, and for its own size.
+        let replacement = spend(&funding[..1], &[49 * COIN - 1000]);
+        assert_eq!(Err(Error::Replacement("insufficient fee")), mempool.add(replacement, &utxos, height));
+
+        let replacement = spend(&funding[..1], &[49 * COIN - 1085]);
+        assert_eq!(Ok(replacement.txid()), mempool.add(replacement.clone(), &utxos, height));
+        assert_eq!(1, mempool.len());
+        assert_eq!(Some(&replacement.txid()), mempool.spenders.get(&funding[0]));
+
+        // the replacement doesn't signal.
+        let other = spend(&funding[..1], &[40 * COIN]);
+        assert_eq!(Err(Error::Conflict(funding[0])), mempool.add(other, &utxos, height));
+    }
+
+    #[test]
+    fn replacement_inputs_test() {
+        let (utxos, funding, height) = funded_utxos(2);
+        let mut mempool = new();
+
+        let original = signaling(spend(&funding[..1], &[49 * COIN, 20 * 1000]));
+        let unrelated = spend(&funding[1..], &[49 * COIN]);
+        mempool.add(original.clone(), &utxos, height).unwrap();
+        mempool.add(unrelated.clone(), &utxos, height).unwrap();
+
+        let spends_original = spend(&[funding[0], outpoint(&original, 1)], &[40 * COIN]);
+        assert_eq!(Err(Error::Replacement("spends conflicting transaction")), mempool.add(spends_original, &utxos, height));
+
+        let adds_unconfirmed = spend(&[funding[0], outpoint(&unrelated, 0)], &[40 * COIN]);
+        assert_eq!(Err(Error::Replacement("replacement-adds-unconfirmed")), mempool.add(adds_unconfirmed, &utxos, height));
+    }
+
+    #[test]
+    fn package_test() {
+        let (utxos, funding, height) = funded_utxos(1);
+        let mut mempool = new();
+
+        // the parent pays no fee, the child pays for both.
+        let parent = spend(&funding, &[50 * COIN]);
+        let child = spend(&[outpoint(&parent, 0)], &[50 * COIN - 169]);
+        assert_eq!(Err(Error::MinRelayFee { fee: 0, min: 85 }), mempool.add(parent.clone(), &utxos, height));
+
+        assert_eq!(Err(Error::Package("package-not-sorted")), mempool.add_package(&[child.clone(), parent.clone()], &utxos, height));
+        assert_eq!(Err(Error::Package("package-not-child-with-parents")), mempool.add_package(&[parent.clone(), spend(&funding, &[1000])], &utxos, height));
+
+        // 1 satoshi short for the 170 virtual bytes of the package.
+        assert_eq!(Err(Error::MinRelayFee { fee: 169, min: 170 }), mempool.add_package(&[parent.clone(), child], &utxos, height));
+        assert!(mempool.is_empty());
+
+        let child = spend(&[outpoint(&parent, 0)], &[50 * COIN - 170]);
+        assert_eq!(Ok(vec![parent.txid(), child.txid()]), mempool.add_package(&[parent.clone(), child.clone()], &utxos, height));
+        assert_eq!(Err(Error::AlreadyInMempool), mempool.add(child, &utxos, height));
+    }
+
+    #[test]
+    fn prioritise_test() {
+        let (utxos, funding, height) = funded_utxos(1);
+        let mut mempool = new();
+
+        // the delta pays the minimum relay fee, but not the coinbase.
+        let tx = spend(&funding, &[50 * COIN]);
+        mempool.prioritise(tx.txid(), 85);
+        assert_eq!(Ok(tx.txid()), mempool.add(tx.clone(), &utxos, height));
+
+        let entry = mempool.get(&tx.txid()).unwrap();
+        assert_eq!((0, 85), (entry.fee, entry.modified_fee()));
+
+        mempool.prioritise(tx.txid(), -85);
+        assert_eq!(0, mempool.get(&tx.txid()).unwrap().fee_delta);
+        assert!(mempool.deltas.is_empty());
+    }
+
+    #[test]
+    fn orphans_test() {
+        let (utxos, funding, height) = funded_utxos(1);
+        let mut mempool = new();
+
+        let parent = spend(&funding, &[49 * COIN]);
+        let child = spend(&[outpoint(&parent, 0)], &[48 * COIN]);
+        let grandchild = spend(&[outpoint(&child, 0)], &[47 * COIN]);
+        let invalid = spend(&[outpoint(&parent, 0)], &[50 * COIN]);
+
+        for tx in [&grandchild, &invalid, &child] {
+            assert!(matches!(mempool.add(tx.clone(), &utxos, height), Err(Error::MissingInput(_))));
+        };
+        assert_eq!(3, mempool.orphans().len());
+
+        // the grandchild waits for the child, which waits for the parent.
+        assert_eq!(Ok(parent.txid()), mempool.add(parent.clone(), &utxos, height));
+        assert_eq!(3, mempool.len());
+        assert!(mempool.contains(&grandchild.txid()));
+        assert!(mempool.orphans().is_empty());
+
+        // the parent is known, its output doesn't exist.
+        assert!(mempool.add(spend(&[outpoint(&parent, 1)], &[1000]), &utxos, height).is_err());
+        assert!(mempool.orphans().is_empty());
+    }
+
+    #[test]
+    fn dump_and_load_test() {
+        let (utxos, funding, height) = funded_utxos(2);
+        let mut mempool = new();
+
+        let parent = spend(&funding[..1], &[49 * COIN]);
+        let child = spend(&[outpoint(&parent, 0)], &[48 * COIN]);
+        let other = spend(&funding[1..], &[49 * COIN]);
+
+        for tx in [&child, &parent, &other] {
+            mempool.prioritise(tx.txid(), 1000);
+        };
+        for tx in [&parent, &child, &other] {
+            mempool.add(tx.clone(), &utxos, height).unwrap();
+        };
+        mempool.entries.get_mut(&parent.txid()).unwrap().time = 1_600_000_000;
+        mempool.prioritise(u256::new(7), -500);
+
+        let data_dir = crate::blockstore::test::temp_data_dir("mempool_dump");
+        let path = data_dir.join(MEMPOOL_FILE);
+        mempool.dump(&path).unwrap();
+
+        let mut loaded = new();
+        assert_eq!(Ok(3), loaded.load(&path, &utxos, height).map_err(|error| error.to_string()));
+        for txid in [parent.txid(), child.txid(), other.txid()] {
+            assert_eq!(mempool.get(&txid), loaded.get(&txid));
+        };
+        assert_eq!(-500, loaded.fee_delta(&u256::new(7)));
+
+        // the output spent by the other transaction is gone, it's dropped but keeps its delta.
+        let (utxos, _, _) = funded_utxos(1);
+        let mut loaded = new();
+        assert_eq!(Ok(2), loaded.load(&path, &utxos, height).map_err(|error| error.to_string()));
+        assert!(!loaded.contains(&other.txid()));
+        assert_eq!(1000, loaded.fee_delta(&other.txid()));
+
+        let mut empty = new();
+        assert_eq!(Ok(0), empty.load(&path.with_extension("missing"), &utxos, height).map_err(|error| error.to_string()));
+    }
+
+    #[test]
+    fn remove_for_block_test() {
+        let (utxos, funding, height) = funded_utxos(2);
+        let mut mempool = new();
+
+        let parent = spend(&funding[..1], &[49 * COIN]);
+        let child = spend(&[outpoint(&parent, 0)], &[48 * COIN]);
+        let other = spend(&funding[1..], &[49 * COIN]);
+        let grandchild = spend(&[outpoint(&other, 0)], &[48 * COIN]);
+
+        for tx in [&parent, &child, &other, &grandchild] {
+            mempool.add(tx.clone(), &utxos, height).unwrap();
+        };
+
+        // the block mines the parent, and a double spend of the other transaction.
+        let double_spend = spend(&funding[1..], &[40 * COIN]);
+        let block = Block { header: block::genesis().header, transactions: vec![coinbase(b"2"), parent, double_spend] };
+        mempool.remove_for_block(&block);
+
+        assert_eq!(1, mempool.len());
+        assert!(mempool.contains(&child.txid()));
+        assert!(mempool.parents(&child.txid()).is_empty());
+    }
+}
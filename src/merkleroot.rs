@@ -1,8 +1,14 @@
 use crate::hash::hash256;
 use crate::transactions::Transaction;
+use crate::u256;
 
 // implementation comes from https://learnmeabitcoin.com/technical/merkle-root
 pub fn from_txids(txids: Vec<String>) -> String {
+    // a single transaction is its own merkle root.
+    if txids.len() == 1 {
+        return txids[0].to_string();
+    };
+
     let mut result: Vec<String> = vec![];
 
     for txid_chunk in txids.chunks(2) {
@@ -16,14 +22,10 @@ pub fn from_txids(txids: Vec<String>) -> String {
         result.push(hex::encode(hash256(&bytes)));
     };
 
-    if result.len() == 1 {
-        return result.first().unwrap().to_string();
-    };
-
     from_txids(result)
 }
 
-pub fn from_transactions(transactions: &Vec<Transaction>) -> String {
+pub fn from_transactions(transactions: &[Transaction]) -> String {
     let txids: Vec<_> = transactions
         .iter()
         .map(|tx| tx.id())
@@ -32,6 +34,19 @@ pub fn from_transactions(transactions: &Vec<Transaction>) -> String {
     from_txids(txids)
 }
 
+// https://github.com/bitcoin/bips/blob/master/bip-0141.mediawiki#commitment-structure
+pub fn witness_root_from_transactions(transactions: &[Transaction]) -> String {
+    // the coinbase wtxid is replaced by 0x00..00, since the coinbase holds the commitment itself.
+    let wtxids: Vec<_> = transactions
+        .iter()
+        .enumerate()
+        .map(|(i, tx)| if i == 0 { u256::new(0) } else { tx.wtxid() })
+        .map(|wtxid| hex::encode(wtxid.to_le_bytes()))
+        .collect();
+
+    from_txids(wtxids)
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -50,7 +65,7 @@ mod test {
             .into_iter()
             .map(|txid| hex::decode(txid).unwrap())
             .map(|bytes| bytes.into_iter().rev().collect::<Vec<u8>>())
-            .map(|bytes| hex::encode(bytes))
+            .map(hex::encode)
             .collect();
 
         assert_eq!("6657a9252aacd5c0b2940996ecff952228c3067cc38d4885efb5a4ac4247e9f3", from_txids(txids))
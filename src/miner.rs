@@ -1,62 +1,150 @@
-use std::time::SystemTime;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::hint;
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
 
-use crate::block::{Block, Header};
+use crate::block::{self, Block, Header};
+use crate::blocktemplate::BlockTemplate;
 use crate::difficulty;
 use crate::merkleroot;
+use crate::script::Script;
 use crate::transactions::{Transaction, TxIn, TxOut};
 use crate::u256;
+use crate::validation;
 
-pub struct Miner {}
+// The nounce only has 4 billion values, which a real difficulty easily exhausts. Each worker
+// thread takes its own extranonce, written in the coinbase scriptSig, which changes the merkle
+// root and so gives a fresh nounce space. The timestamp is refreshed along with it.
 
-pub fn new() -> Miner { Miner{} }
+/// Maximum size of the coinbase tag, which keeps the coinbase scriptSig below its 100 bytes limit.
+pub const MAX_TAG_SIZE: usize = 80;
+
+/// Number of hashes between two checks of whether the search should stop.
+const CANCEL_CHECK_INTERVAL: u32 = 1 << 16;
+
+pub struct Miner {
+    /// Where the coinbase pays the block reward.
+    script_pub_key: Script,
+    /// A message in the coinbase scriptSig, after the block height and the extranonce.
+    tag: Vec<u8>,
+    threads: usize,
+    /// Number of hashes computed since the miner was created, by every thread.
+    hashes: AtomicU64,
+}
+
+/// A miner with one worker thread per available core.
+pub fn new(script_pub_key: Script, tag: &[u8]) -> Miner {
+    let threads = thread::available_parallelism().map(|n| n.get()).unwrap_or(1);
+
+    new_with_threads(script_pub_key, tag, threads)
+}
+
+pub fn new_with_threads(script_pub_key: Script, tag: &[u8], threads: usize) -> Miner {
+    assert!(tag.len() <= MAX_TAG_SIZE, "coinbase tag of {} bytes", tag.len());
+    assert!(threads > 0, "a miner needs at least one thread");
+
+    Miner { script_pub_key, tag: tag.to_vec(), threads, hashes: AtomicU64::new(0) }
+}
 
 impl Miner {
-    pub fn next(&self, previous_block: &Block, target_bits: u32) -> Block {
-        let target = difficulty::bits_to_target(target_bits);
-        let mut candidate_block = self.build_candidate_block(previous_block, target_bits);
+    pub fn threads(&self) -> usize {
+        self.threads
+    }
 
-        loop {
-            candidate_block.header.nounce += 1;
+    /// Number of hashes computed so far, updated every few thousand hashes while mining.
+    pub fn hashes(&self) -> u64 {
+        self.hashes.load(Ordering::Relaxed)
+    }
 
-            if candidate_block.hash() < target {
-                return candidate_block;
-            }
-        }
+    /// Mines a block with the transactions of the template. Its coinbase claims the subsidy and
+    /// the fees, and starts with the block height.
+    pub fn next(&self, template: &BlockTemplate) -> Block {
+        self.mine(template, &AtomicBool::new(false)).unwrap()
     }
 
-    fn build_coinbase_transaction(&self) -> Transaction {
+    /// Like `next`, but gives up and returns None once `cancel` is set, e.g. when another block
+    /// extends the tip the template was built on.
+    pub fn mine(&self, template: &BlockTemplate, cancel: &AtomicBool) -> Option<Block> {
+        let target = difficulty::bits_to_target(template.context.bits);
+        let extranonce = AtomicU64::new(0);
+        let found = AtomicBool::new(false);
+
+        thread::scope(|scope| {
+            let workers: Vec<_> = (0..self.threads)
+                .map(|_| scope.spawn(|| {
+                    let stop = || found.load(Ordering::Relaxed) || cancel.load(Ordering::Relaxed);
+
+                    while !stop() {
+                        let mut candidate_block = self.build_candidate_block(template, extranonce.fetch_add(1, Ordering::Relaxed));
+
+                        if search_nounce(&mut candidate_block, target, &stop, &self.hashes) {
+                            found.store(true, Ordering::Relaxed);
+                            return Some(candidate_block)
+                        };
+                    };
+
+                    None
+                }))
+                .collect();
+
+            workers.into_iter().filter_map(|worker| worker.join().unwrap()).next()
+        })
+    }
+
+    fn build_coinbase_transaction(&self, template: &BlockTemplate, extranonce: u64) -> Transaction {
+        // the height is always included, even before BIP34 is enforced.
+        let mut script_sig = validation::coinbase_height(template.context.height);
+        script_sig.push_slice(&extranonce.to_le_bytes());
+        script_sig.push_slice(&self.tag);
+
+        let mut outputs = vec![
+            TxOut {
+                value: template.coinbase_value,
+                script_pub_key: self.script_pub_key.clone(),
+            }
+        ];
+
+        // the witness reserved value of the commitment.
+        let witness = match &template.witness_commitment {
+            Some(commitment) => {
+                outputs.push(TxOut { value: 0, script_pub_key: commitment.clone() });
+                vec![vec![0; 32]]
+            },
+            None => vec![],
+        };
+
         Transaction {
             version: 1,
             inputs: vec![
                 TxIn {
                     txid: u256::new(0),
                     vout: 0xffffffff,
-                    script_sig: hex::encode("rcoin miner"),
+                    script_sig,
                     sequence: 0xffffffff,
+                    witness,
                 }
             ],
-            outputs: vec![
-                TxOut {
-                    value: 25 * 100_000_000,
-                    script_pub_key: hex::encode("todo: locking script :)")
-                }
-            ],
+            outputs,
             lock_time: 0,
         }
     }
 
-    fn build_candidate_block(&self, previous_block: &Block, target_bits: u32) -> Block {
-        let transactions = vec![self.build_coinbase_transaction()];
+    fn build_candidate_block(&self, template: &BlockTemplate, extranonce: u64) -> Block {
+        let mut transactions = vec![self.build_coinbase_transaction(template, extranonce)];
+        transactions.extend(template.transactions().cloned());
+
         let merkleroot = merkleroot::from_transactions(&transactions);
         let merkleroot = hex::decode(merkleroot).unwrap();
+        let context = &template.context;
 
         Block {
             header: Header {
-                version: 0x1,
-                prev_block_hash: previous_block.hash(),
+                version: template.version,
+                prev_block_hash: context.prev_block_hash,
                 merkle_root: u256::from_le_bytes(merkleroot.try_into().unwrap()),
-                time: current_time(),
-                bits: target_bits,
+                // the timestamp must be after the median time past.
+                time: current_time().max(context.median_time_past + 1),
+                bits: context.bits,
                 nounce: 0,
             },
             transactions,
@@ -64,9 +152,123 @@ impl Miner {
     }
 }
 
+/// Tries every nounce of the block until its hash is below the target, counting them in
+/// `hashes`. Returns false once the nounces are exhausted, or when `stop` returns true.
+fn search_nounce(block: &mut Block, target: u256, stop: &impl Fn() -> bool, hashes: &AtomicU64) -> bool {
+    let hasher = block.header.hasher();
+
+    for nounce in 0..=u32::MAX {
+        if nounce % CANCEL_CHECK_INTERVAL == 0 {
+            if nounce > 0 {
+                hashes.fetch_add(CANCEL_CHECK_INTERVAL as u64, Ordering::Relaxed);
+            };
+
+            if stop() {
+                return false
+            };
+        };
+
+        if hasher.hash(nounce) < target {
+            block.header.nounce = nounce;
+            hashes.fetch_add((nounce % CANCEL_CHECK_INTERVAL) as u64 + 1, Ordering::Relaxed);
+            return true
+        };
+    };
+
+    hashes.fetch_add(CANCEL_CHECK_INTERVAL as u64, Ordering::Relaxed);
+    false
+}
+
+/// Hashes per second of a single thread, measured for `duration`. Without `midstate`, the whole
+/// header is serialized and hashed for every nounce, to compare.
+pub fn hashrate(duration: Duration, midstate: bool) -> f64 {
+    let mut header = block::genesis().header;
+    let hasher = header.hasher();
+    let start = Instant::now();
+    let mut hashes: u64 = 0;
+
+    while start.elapsed() < duration {
+        for nounce in 0..CANCEL_CHECK_INTERVAL {
+            if midstate {
+                hint::black_box(hasher.hash(nounce));
+            } else {
+                header.nounce = nounce;
+                hint::black_box(header.hash());
+            };
+        };
+
+        hashes += CANCEL_CHECK_INTERVAL as u64;
+    };
+
+    hashes as f64 / start.elapsed().as_secs_f64()
+}
+
 fn current_time() -> u32 {
     SystemTime::now()
         .duration_since(SystemTime::UNIX_EPOCH)
         .unwrap()
         .as_secs() as u32
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::blocktemplate;
+    use crate::mempool;
+    use crate::params::ChainParams;
+    use crate::utxo;
+    use crate::validation::BlockContext;
+
+    fn template(bits: u32) -> BlockTemplate {
+        blocktemplate::new(context(bits), &mempool::new())
+    }
+
+    fn context(bits: u32) -> BlockContext {
+        let genesis = ChainParams::regtest().genesis;
+
+        BlockContext {
+            height: 1,
+            prev_block_hash: genesis.hash(),
+            bits,
+            subsidy: 50 * validation::COIN,
+            median_time_past: genesis.header.time,
+            now: current_time(),
+            enforce_bip34: true,
+        }
+    }
+
+    #[test]
+    fn next_test() {
+        let template = template(0x207fffff);
+        let block = new_with_threads(Script::from(vec![0x51]), b"test", 4).next(&template);
+
+        assert_eq!(Ok(()), validation::validate_block(&block, &template.context, &utxo::new()));
+    }
+
+    #[test]
+    fn extranonce_test() {
+        let miner = new_with_threads(Script::new(), b"", 1);
+        let block1 = miner.build_candidate_block(&template(0x207fffff), 0);
+        let block2 = miner.build_candidate_block(&template(0x207fffff), 1);
+
+        // height, extranonce and an empty tag.
+        assert_eq!("5108000000000000000000", block1.transactions[0].inputs[0].script_sig.to_hex());
+        assert_ne!(block1.header.merkle_root, block2.header.merkle_root);
+    }
+
+    #[test]
+    fn cancel_test() {
+        // the hardest target, which can't be mined.
+        let miner = new_with_threads(Script::new(), b"", 2);
+        let cancel = AtomicBool::new(false);
+
+        thread::scope(|scope| {
+            scope.spawn(|| {
+                thread::sleep(Duration::from_millis(50));
+                cancel.store(true, Ordering::Relaxed);
+            });
+
+            assert_eq!(None, miner.mine(&template(0x03000001), &cancel));
+        });
+    }
+}
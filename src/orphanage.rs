@@ -0,0 +1,209 @@
+use std::collections::{HashMap, HashSet};
+
+use openssl::rand::rand_bytes;
+
+use crate::block::Block;
+use crate::policy;
+use crate::transactions::{OutPoint, Transaction};
+use crate::u256;
+
+// Transactions spending outputs that aren't known yet, waiting for their parents. They are indexed
+// by the outpoints they spend, so the orphans of a new transaction are found from its outputs.
+//
+// https://github.com/bitcoin/bitcoin/blob/master/src/txorphanage.cpp
+//
+// Anyone can send orphans, which can't be checked without their parents: the pool is bounded,
+// orphans expire, and a random one is evicted when it's full, so an attacker can't choose which.
+
+/// Default maximum number of orphans.
+pub const DEFAULT_MAX_ORPHANS: usize = 100;
+
+/// Seconds an orphan is kept.
+pub const ORPHAN_EXPIRE_TIME: u64 = 20 * 60;
+
+/// Seconds between two sweeps of the expired orphans.
+const ORPHAN_EXPIRE_INTERVAL: u64 = 5 * 60;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Orphan {
+    pub tx: Transaction,
+    /// When the orphan expires, in seconds since the unix epoch.
+    pub expiry: u64,
+}
+
+pub struct Orphanage {
+    orphans: HashMap<u256, Orphan>,
+    /// The orphans spending each outpoint.
+    by_outpoint: HashMap<OutPoint, HashSet<u256>>,
+    max_orphans: usize,
+    next_sweep: u64,
+}
+
+pub fn new() -> Orphanage {
+    new_with_max_orphans(DEFAULT_MAX_ORPHANS)
+}
+
+pub fn new_with_max_orphans(max_orphans: usize) -> Orphanage {
+    Orphanage { orphans: HashMap::new(), by_outpoint: HashMap::new(), max_orphans, next_sweep: 0 }
+}
+
+impl Orphanage {
+    pub fn get(&self, txid: &u256) -> Option<&Orphan> {
+        self.orphans.get(txid)
+    }
+
+    pub fn contains(&self, txid: &u256) -> bool {
+        self.orphans.contains_key(txid)
+    }
+
+    pub fn len(&self) -> usize {
+        self.orphans.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.orphans.is_empty()
+    }
+
+    /// Keeps the transaction until `now` + `ORPHAN_EXPIRE_TIME`, then limits the pool to its
+    /// maximum size. Returns false if it was already kept, or if it's too large: an orphan can't
+    /// be checked, a large one would only waste memory.
+    pub fn add(&mut self, tx: Transaction, now: u64) -> bool {
+        let txid = tx.txid();
+
+        if self.contains(&txid) || tx.weight() > policy::MAX_STANDARD_TX_WEIGHT {
+            return false
+        };
+
+        for txin in &tx.inputs {
+            self.by_outpoint.entry(txin.outpoint()).or_default().insert(txid);
+        };
+        self.orphans.insert(txid, Orphan { tx, expiry: now + ORPHAN_EXPIRE_TIME });
+
+        self.limit_size(now);
+        true
+    }
+
+    pub fn remove(&mut self, txid: &u256) -> Option<Orphan> {
+        let orphan = self.orphans.remove(txid)?;
+
+        for txin in &orphan.tx.inputs {
+            let outpoint = txin.outpoint();
+
+            if let Some(spenders) = self.by_outpoint.get_mut(&outpoint) {
+                spenders.remove(txid);
+
+                if spenders.is_empty() {
+                    self.by_outpoint.remove(&outpoint);
+                };
+            };
+        };
+
+        Some(orphan)
+    }
+
+    /// The orphans spending the outputs of `tx`, sorted.
+    pub fn children(&self, tx: &Transaction) -> Vec<u256> {
+        let txid = tx.txid();
+
+        let mut children: Vec<u256> = (0..tx.outputs.len())
+            .filter_map(|vout| self.by_outpoint.get(&OutPoint { txid, vout: vout as u32 }))
+            .flatten()
+            .copied()
+            .collect::<HashSet<u256>>()
+            .into_iter()
+            .collect();
+        children.sort();
+        children
+    }
+
+    /// Removes the orphans mined in the block, and the ones spending the same outputs.
+    pub fn remove_for_block(&mut self, block: &Block) {
+        for tx in block.transactions.iter().filter(|tx| !tx.is_coinbase()) {
+            for txin in &tx.inputs {
+                for txid in self.by_outpoint.get(&txin.outpoint()).cloned().into_iter().flatten() {
+                    self.remove(&txid);
+                };
+            };
+        };
+    }
+
+    /// Removes the expired orphans, at most every `ORPHAN_EXPIRE_INTERVAL`, then random orphans
+    /// until the pool isn't larger than its maximum size.
+    pub fn limit_size(&mut self, now: u64) {
+        if now >= self.next_sweep {
+            let expired: Vec<u256> = self.orphans.iter()
+                .filter(|(_, orphan)| orphan.expiry <= now)
+                .map(|(txid, _)| *txid)
+                .collect();
+
+            for txid in expired {
+                self.remove(&txid);
+            };
+
+            self.next_sweep = now + ORPHAN_EXPIRE_INTERVAL;
+        };
+
+        while self.orphans.len() > self.max_orphans {
+            // sorted, so only the random bytes decide which one goes.
+            let mut txids: Vec<u256> = self.orphans.keys().copied().collect();
+            txids.sort();
+
+            let mut random = [0; 8];
+            rand_bytes(&mut random).unwrap();
+
+            let evicted = txids[(u64::from_le_bytes(random) % txids.len() as u64) as usize];
+            self.remove(&evicted);
+        };
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::transactions::test::{coinbase, outpoint, spend};
+    use crate::validation::COIN;
+
+    #[test]
+    fn add_and_remove_test() {
+        let mut orphanage = new();
+        let parent = spend(&[outpoint(&coinbase(b"1"), 0)], &[49 * COIN, 49 * COIN]);
+        let child = spend(&[outpoint(&parent, 0)], &[48 * COIN]);
+        let other_child = spend(&[outpoint(&parent, 1)], &[48 * COIN]);
+
+        assert!(orphanage.add(child.clone(), 0));
+        assert!(orphanage.add(other_child.clone(), 0));
+        assert!(!orphanage.add(child.clone(), 0));
+
+        let mut children = vec![child.txid(), other_child.txid()];
+        children.sort();
+        assert_eq!(children, orphanage.children(&parent));
+
+        assert_eq!(Some(child.clone()), orphanage.remove(&child.txid()).map(|orphan| orphan.tx));
+        assert_eq!(vec![other_child.txid()], orphanage.children(&parent));
+
+        // a block spending the same output as the other child.
+        let double_spend = spend(&[outpoint(&parent, 1)], &[47 * COIN]);
+        orphanage.remove_for_block(&Block { header: crate::block::genesis().header, transactions: vec![double_spend] });
+        assert!(orphanage.is_empty());
+        assert!(orphanage.by_outpoint.is_empty());
+    }
+
+    #[test]
+    fn limit_size_test() {
+        let mut orphanage = new_with_max_orphans(5);
+        let orphans: Vec<Transaction> = (0..9u32)
+            .map(|i| spend(&[outpoint(&coinbase(&i.to_le_bytes()), 0)], &[49 * COIN]))
+            .collect();
+
+        for tx in &orphans[..8] {
+            orphanage.add(tx.clone(), 1000);
+        };
+        assert_eq!(5, orphanage.len());
+        assert_eq!(5, orphanage.by_outpoint.len());
+
+        // the others expire, the new one stays.
+        orphanage.add(orphans[8].clone(), 1000 + ORPHAN_EXPIRE_TIME);
+        assert_eq!(1, orphanage.len());
+        assert!(orphanage.contains(&orphans[8].txid()));
+    }
+}
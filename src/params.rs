@@ -0,0 +1,189 @@
+use std::fmt;
+use std::str::FromStr;
+
+use crate::block::{self, Block};
+use crate::u256;
+
+// Everything that differs between networks, like bitcoin core's CChainParams.
+//
+// https://github.com/bitcoin/bitcoin/blob/master/src/kernel/chainparams.cpp
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Network {
+    Mainnet,
+    Testnet,
+    Signet,
+    Regtest,
+}
+
+#[derive(Clone, Debug)]
+pub struct ChainParams {
+    pub network: Network,
+    pub genesis: Block,
+    /// The easiest target allowed.
+    pub pow_limit: u256,
+    /// Whether the difficulty stays the same forever, instead of being adjusted every 2016 blocks.
+    pub pow_no_retargeting: bool,
+    /// Whether a block more than 20 minutes after its parent can be mined at the easiest
+    /// difficulty, like on testnet.
+    pub pow_allow_min_difficulty_blocks: bool,
+    /// Number of blocks after which the block subsidy is halved.
+    pub subsidy_halving_interval: u32,
+    /// Height from which the coinbase must start with the block height, see BIP34.
+    pub bip34_height: u32,
+    /// Prefix of the framed blocks in the block files, and of the network messages.
+    pub magic: [u8; 4],
+    /// Base58Check version of pay to public key hash addresses.
+    pub pubkey_address_prefix: u8,
+    /// Base58Check version of pay to script hash addresses.
+    pub script_address_prefix: u8,
+    /// Human readable part of bech32 (segwit) addresses.
+    pub bech32_hrp: &'static str,
+    /// Subdirectory of the data directory where the network files are written.
+    pub data_subdir: &'static str,
+}
+
+impl ChainParams {
+    pub fn mainnet() -> ChainParams {
+        ChainParams {
+            network: Network::Mainnet,
+            genesis: block::genesis(),
+            pow_limit: u256::from_words(0x00000000ffffffffffffffffffffffff, u128::MAX),
+            pow_no_retargeting: false,
+            pow_allow_min_difficulty_blocks: false,
+            subsidy_halving_interval: 210_000,
+            bip34_height: 227_931,
+            magic: [0xf9, 0xbe, 0xb4, 0xd9],
+            pubkey_address_prefix: 0x00,
+            script_address_prefix: 0x05,
+            bech32_hrp: "bc",
+            data_subdir: "",
+        }
+    }
+
+    pub fn testnet() -> ChainParams {
+        ChainParams {
+            network: Network::Testnet,
+            genesis: block::create_genesis(1296688602, 414098458, 0x1d00ffff),
+            pow_allow_min_difficulty_blocks: true,
+            bip34_height: 21_111,
+            magic: [0x0b, 0x11, 0x09, 0x07],
+            pubkey_address_prefix: 0x6f,
+            script_address_prefix: 0xc4,
+            bech32_hrp: "tb",
+            data_subdir: "testnet3",
+            ..ChainParams::mainnet()
+        }
+    }
+
+    /// The default signet. Its blocks are also signed by a few known keys, but these signatures
+    /// (the signet challenge) aren't checked: only the proof of work is.
+    pub fn signet() -> ChainParams {
+        ChainParams {
+            network: Network::Signet,
+            genesis: block::create_genesis(1598918400, 52613770, 0x1e0377ae),
+            pow_limit: u256::from_words(0x00000377ae0000000000000000000000, 0),
+            pow_allow_min_difficulty_blocks: false,
+            bip34_height: 1,
+            magic: [0x0a, 0x03, 0xcf, 0x40],
+            data_subdir: "signet",
+            ..ChainParams::testnet()
+        }
+    }
+
+    /// A local network where blocks are trivial to mine, for tests.
+    pub fn regtest() -> ChainParams {
+        ChainParams {
+            network: Network::Regtest,
+            genesis: block::create_genesis(1296688602, 2, 0x207fffff),
+            pow_limit: u256::from_words(0x7fffff00000000000000000000000000, 0),
+            pow_no_retargeting: true,
+            subsidy_halving_interval: 150,
+            bip34_height: 1,
+            magic: [0xfa, 0xbf, 0xb5, 0xda],
+            bech32_hrp: "bcrt",
+            data_subdir: "regtest",
+            ..ChainParams::testnet()
+        }
+    }
+
+    pub fn from_network(network: Network) -> ChainParams {
+        match network {
+            Network::Mainnet => ChainParams::mainnet(),
+            Network::Testnet => ChainParams::testnet(),
+            Network::Signet => ChainParams::signet(),
+            Network::Regtest => ChainParams::regtest(),
+        }
+    }
+}
+
+impl fmt::Display for Network {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Network::Mainnet => "main",
+            Network::Testnet => "test",
+            Network::Signet => "signet",
+            Network::Regtest => "regtest",
+        };
+
+        write!(f, "{}", name)
+    }
+}
+
+/// Parses the network names of bitcoin core's -chain option.
+impl FromStr for Network {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Network, String> {
+        match name {
+            "main" => Ok(Network::Mainnet),
+            "test" => Ok(Network::Testnet),
+            "signet" => Ok(Network::Signet),
+            "regtest" => Ok(Network::Regtest),
+            _ => Err(format!("unknown network: {}", name)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::difficulty;
+    use crate::validation;
+
+    #[test]
+    fn genesis_hashes_test() {
+        let expected = [
+            (Network::Mainnet, "000000000019d6689c085ae165831e934ff763ae46a2a6c172b3f1b60a8ce26f"),
+            (Network::Testnet, "000000000933ea01ad0ee984209779baaec3ced90fa3f408719526f8d77f4943"),
+            (Network::Signet, "00000008819873e925422c1ff0f99f7cc9bbb232af63a077a480a3633bee1ef6"),
+            (Network::Regtest, "0f9188f13cb7b2c71f2a335e3a4fc328bf5beb436012afca590b1a11466e2206"),
+        ];
+
+        for (network, hash) in expected {
+            let params = ChainParams::from_network(network);
+
+            assert_eq!(hash, params.genesis.hash_hex());
+            assert_eq!(Ok(()), validation::check_block(&params.genesis));
+        };
+    }
+
+    #[test]
+    fn pow_limit_test() {
+        for network in [Network::Mainnet, Network::Testnet, Network::Signet, Network::Regtest] {
+            let params = ChainParams::from_network(network);
+
+            // the genesis block is mined at the easiest difficulty.
+            assert_eq!(params.genesis.header.bits, difficulty::target_to_bits(params.pow_limit));
+        };
+    }
+
+    #[test]
+    fn network_from_str_test() {
+        for network in [Network::Mainnet, Network::Testnet, Network::Signet, Network::Regtest] {
+            assert_eq!(Ok(network), network.to_string().parse());
+        };
+
+        assert_eq!(Err(String::from("unknown network: mainnet")), "mainnet".parse::<Network>());
+    }
+}
@@ -0,0 +1,175 @@
+use crate::encoding;
+use crate::script::ScriptType;
+use crate::transactions::{Transaction, TxOut};
+
+// The rules a transaction must follow to be relayed and accepted in the mempool, on top of the
+// consensus rules. A block can still include non standard transactions.
+//
+// https://github.com/bitcoin/bitcoin/blob/master/src/policy/policy.cpp
+
+/// Maximum weight of a standard transaction, a tenth of the block weight.
+pub const MAX_STANDARD_TX_WEIGHT: usize = 400_000;
+
+/// Smaller transactions, without their witness, could be confused with the 64 bytes inner nodes
+/// of the merkle tree.
+pub const MIN_STANDARD_TX_NONWITNESS_SIZE: usize = 65;
+
+/// Enough for a P2SH input spending a 15 of 15 multisig.
+pub const MAX_STANDARD_SCRIPTSIG_SIZE: usize = 1650;
+
+/// Maximum size of an OP_RETURN output script.
+pub const MAX_OP_RETURN_RELAY: usize = 83;
+
+pub const MAX_STANDARD_VERSION: u32 = 2;
+
+/// The minimum fee rate of relayed transactions, in satoshis per 1000 virtual bytes.
+pub const MIN_RELAY_FEE_RATE: u64 = 1000;
+
+/// The fee rate a replacement must pay for its own size, on top of the fees of the transactions
+/// it replaces, in satoshis per 1000 virtual bytes.
+pub const INCREMENTAL_RELAY_FEE_RATE: u64 = 1000;
+
+/// Maximum number of transactions in a package.
+pub const MAX_PACKAGE_COUNT: usize = 25;
+
+/// Maximum weight of a package, enough for a standard transaction and a small child.
+pub const MAX_PACKAGE_WEIGHT: usize = 404_000;
+
+/// The fee rate an output is checked against: an output is dust if it would cost more than a
+/// third of its value to spend it at this fee rate, in satoshis per 1000 virtual bytes.
+pub const DUST_RELAY_FEE_RATE: u64 = 3000;
+
+/// Checks the version, size, scriptSigs and outputs of the transaction. Fails with the reason,
+/// using bitcoin core's reject reasons.
+pub fn check_standard(tx: &Transaction) -> Result<(), &'static str> {
+    if tx.version == 0 || tx.version > MAX_STANDARD_VERSION {
+        return Err("version")
+    };
+
+    if tx.weight() > MAX_STANDARD_TX_WEIGHT {
+        return Err("tx-size")
+    };
+
+    if tx.to_bytes_without_witness().len() < MIN_STANDARD_TX_NONWITNESS_SIZE {
+        return Err("tx-size-small")
+    };
+
+    for txin in &tx.inputs {
+        if txin.script_sig.len() > MAX_STANDARD_SCRIPTSIG_SIZE {
+            return Err("scriptsig-size")
+        };
+
+        if !txin.script_sig.is_push_only() {
+            return Err("scriptsig-not-pushonly")
+        };
+    };
+
+    let mut null_data = 0;
+    for output in &tx.outputs {
+        match output.script_pub_key.script_type() {
+            None => return Err("scriptpubkey"),
+            Some(ScriptType::NullData) if output.script_pub_key.len() > MAX_OP_RETURN_RELAY => return Err("scriptpubkey"),
+            Some(ScriptType::NullData) => null_data += 1,
+            Some(_) if is_dust(output) => return Err("dust"),
+            Some(_) => {},
+        };
+    };
+
+    if null_data > 1 {
+        return Err("multi-op-return")
+    };
+
+    Ok(())
+}
+
+/// The value below which the output is dust: spending it costs more than a third of its value,
+/// at `DUST_RELAY_FEE_RATE`. Unspendable outputs are never dust.
+pub fn dust_threshold(output: &TxOut) -> u64 {
+    if output.script_pub_key.script_type() == Some(ScriptType::NullData) {
+        return 0
+    };
+
+    // the size of the input spending it: the outpoint, the sequence, and a signature and a public
+    // key in the scriptSig, or in the witness at a quarter of the weight.
+    let input_size = match output.script_pub_key.witness_program() {
+        Some(_) => 32 + 4 + 1 + (107 / 4) + 4,
+        None => 32 + 4 + 1 + 107 + 4,
+    };
+
+    let size = encoding::serialize(output).len() + input_size;
+    fee_for_size(size, DUST_RELAY_FEE_RATE)
+}
+
+pub fn is_dust(output: &TxOut) -> bool {
+    output.value < dust_threshold(output)
+}
+
+/// The fee of `vsize` virtual bytes at `fee_rate` satoshis per 1000 virtual bytes.
+pub fn fee_for_size(vsize: usize, fee_rate: u64) -> u64 {
+    vsize as u64 * fee_rate / 1000
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::script::Script;
+    use crate::transactions::TxIn;
+    use crate::u256;
+
+    fn tx(outputs: Vec<TxOut>) -> Transaction {
+        Transaction {
+            version: 2,
+            inputs: vec![TxIn {
+                txid: u256::new(1),
+                vout: 0,
+                script_sig: Script::new(),
+                sequence: 0xffffffff,
+                witness: vec![vec![0xab; 72], vec![0xcd; 33]],
+            }],
+            outputs,
+            lock_time: 0,
+        }
+    }
+
+    fn output(value: u64, script: &str) -> TxOut {
+        TxOut { value, script_pub_key: Script::from_hex(script).unwrap() }
+    }
+
+    const P2PKH: &str = "76a914db4d1141d0048b1ed15839d0b7a4c488cd368b0e88ac";
+    const P2WPKH: &str = "0014db4d1141d0048b1ed15839d0b7a4c488cd368b0e";
+
+    #[test]
+    fn dust_threshold_test() {
+        // the thresholds of bitcoin core.
+        assert_eq!(546, dust_threshold(&output(0, P2PKH)));
+        assert_eq!(294, dust_threshold(&output(0, P2WPKH)));
+        assert_eq!(0, dust_threshold(&output(0, "6a")));
+
+        assert!(is_dust(&output(293, P2WPKH)));
+        assert!(!is_dust(&output(294, P2WPKH)));
+    }
+
+    #[test]
+    fn check_standard_test() {
+        assert_eq!(Ok(()), check_standard(&tx(vec![output(1000, P2PKH), output(0, "6a0568656c6c6f")])));
+
+        let mut version = tx(vec![output(1000, P2PKH)]);
+        version.version = 3;
+        assert_eq!(Err("version"), check_standard(&version));
+
+        assert_eq!(Err("tx-size-small"), check_standard(&tx(vec![output(1000, "6a")])));
+        assert_eq!(Err("scriptpubkey"), check_standard(&tx(vec![output(1000, P2PKH), output(1000, "51")])));
+        assert_eq!(Err("dust"), check_standard(&tx(vec![output(545, P2PKH)])));
+        assert_eq!(Err("multi-op-return"), check_standard(&tx(vec![output(1000, P2PKH), output(0, "6a"), output(0, "6a")])));
+
+        let op_return = format!("6a4c51{}", "ab".repeat(81));
+        assert_eq!(Err("scriptpubkey"), check_standard(&tx(vec![output(1000, P2PKH), output(0, &op_return)])));
+
+        let mut script_sig = tx(vec![output(1000, P2PKH)]);
+        script_sig.inputs[0].script_sig = Script::from_hex("0076").unwrap();
+        assert_eq!(Err("scriptsig-not-pushonly"), check_standard(&script_sig));
+
+        let large = tx((0..3300).map(|_| output(1000, P2WPKH)).collect());
+        assert_eq!(Err("tx-size"), check_standard(&large));
+    }
+}
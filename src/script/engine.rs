@@ -1,51 +1,89 @@
-use crate::hash::sha256;
+use crate::hash::{hash160, sha256};
 use crate::script::opcodes::*;
+use crate::script::{self, Instruction, Script, ScriptType};
+
+// Only a few opcodes are supported: the pushes, OP_ADD, OP_EQUAL and the hashes. Any other opcode
+// fails the script, so an output locked by a signature (OP_CHECKSIG...) can't be spent yet, and
+// neither can a witness program.
+//
+// Numbers are read like bitcoin core's CScriptNum: little endian, with the highest bit of the last
+// byte as the sign, and at most 4 bytes long. A result can be longer, but can't be used as an
+// operand.
+
+/// Maximum size of a number operand, in bytes.
+const MAX_NUMBER_SIZE: usize = 4;
 
 pub fn new() -> Engine {
     Engine {
-        script: vec![],
         stack: vec![],
-        pc: 0,
     }
 }
 
 pub struct Engine {
-    // the script to execute.
-    script: Vec<u8>,
-
     // the stack for the execution of the script.
     stack: Vec<Vec<u8>>,
-
-    // the program counter is the index of the current instruction.
-    pc: usize,
 }
 
 impl Engine {
-    pub fn execute(&mut self, script: Vec<u8>) -> Result<bool, String> {
-        self.script = script;
+    pub fn execute(&mut self, script: &Script) -> Result<bool, String> {
         self.stack = vec![];
-        self.pc = 0;
+        self.run(script)?;
+
+        Ok(self.succeeded())
+    }
+
+    /// Checks that `script_sig` unlocks an output locked by `script_pub_key`: the scriptSig is
+    /// run, then the scriptPubKey on the stack it leaves. For a P2SH output, the redeem script
+    /// pushed last by the scriptSig is then run on the rest of the stack (BIP16).
+    ///
+    /// Witness programs can't be verified yet, they fail.
+    pub fn verify(&mut self, script_sig: &Script, script_pub_key: &Script) -> Result<bool, String> {
+        if script_pub_key.witness_program().is_some() {
+            return Err(String::from("witness programs can't be verified yet."))
+        };
 
-        let end = self.script.len();
+        self.stack = vec![];
+        self.run(script_sig)?;
+        let script_sig_stack = self.stack.clone();
 
-        while self.pc < end {
-            let op_code = self.script[self.pc];
-            self.pc += 1;
+        self.run(script_pub_key)?;
+        if !self.succeeded() {
+            return Ok(false)
+        };
 
-            println!("=================================");
-            let result = match op_code {
-                op if op < OP_PUSHDATA1 => self.op_push_data(op as usize),
-                OP_ADD => self.op_add(),
-                OP_EQUAL => self.op_equal(),
-                OP_SHA256 => self.op_sha256(),
-                _ => Ok(()),
+        // https://github.com/bitcoin/bips/blob/master/bip-0016.mediawiki
+        if script_pub_key.script_type() == Some(ScriptType::ScriptHash) {
+            if !script_sig.is_push_only() {
+                return Err(String::from("P2SH: the scriptSig must only push data."))
             };
 
-            if let Err(error) = result {
-                return Err(error);
-            }
+            self.stack = script_sig_stack;
+            let redeem_script = Script::from(self.stack.pop().unwrap());
+            self.run(&redeem_script)?;
+
+            return Ok(self.succeeded())
+        };
+
+        Ok(true)
+    }
+
+    fn run(&mut self, script: &Script) -> Result<(), String> {
+        for instruction in script.instructions() {
+            match instruction? {
+                Instruction::PushBytes(data) => self.op_push_data(data),
+                Instruction::Op(op @ (OP_1NEGATE | OP_1..=OP_16)) => self.op_push_number(op),
+                Instruction::Op(OP_ADD) => self.op_add(),
+                Instruction::Op(OP_EQUAL) => self.op_equal(),
+                Instruction::Op(OP_SHA256) => self.op_sha256(),
+                Instruction::Op(OP_HASH160) => self.op_hash160(),
+                Instruction::Op(op) => Err(format!("{}: not supported.", script::opcodes::name(op))),
+            }?;
         }
 
+        Ok(())
+    }
+
+    fn succeeded(&mut self) -> bool {
         // https://learnmeabitcoin.com/technical/script
         // The script is invalid if:
         // - The final stack is empty
@@ -53,36 +91,39 @@ impl Engine {
         // - There is more than one element left on the stack at the end of execution. 1
         // - The script exits prematurely (e.g. OP_RETURN in a NULL DATA script).
         if self.stack.len() > 1 {
-            return Ok(false)
+            return false
         };
 
         match self.stack.pop() {
-            None => Ok(false),
-            Some(v) => Ok(v.iter().any(|&x| x != 0)),
+            None => false,
+            Some(v) => v.iter().any(|&x| x != 0),
         }
     }
 
-    fn op_push_data(&mut self, size: usize) -> Result<(), String> {
-        let data = &self.script[(self.pc)..(self.pc + size)];
-
+    fn op_push_data(&mut self, data: &[u8]) -> Result<(), String> {
         self.stack.push(data.to_vec());
-        self.pc += size;
+        Ok(())
+    }
+
+    fn op_push_number(&mut self, op: u8) -> Result<(), String> {
+        let n = op as i64 - OP_1 as i64 + 1;
+        self.stack.push(script::script_number(n));
         Ok(())
     }
 
     fn op_add(&mut self) -> Result<(), String> {
-        let a = self.pop_i32();
-        let b = self.pop_i32();
+        let a = self.stack.pop();
+        let b = self.stack.pop();
 
         match (a, b) {
             (Some(a), Some(b)) => {
-                let result = a + b;
-                self.stack.push(smallest_i32_bytes(result));
+                // two 4 bytes operands can't overflow an i64.
+                let result = read_number(&a)? + read_number(&b)?;
+                self.stack.push(script::script_number(result));
                 Ok(())
             }
             _ => Err(String::from("OP_ADD: missing values on stack."))
         }
-
     }
 
     fn op_equal(&mut self) -> Result<(), String> {
@@ -91,10 +132,6 @@ impl Engine {
 
         match (a, b) {
             (Some(a), Some(b)) => {
-                println!("OP_EQUAL");
-                println!("a: {:x?}", a);
-                println!("b: {:x?}", b);
-
                 let result: Vec<u8> = if a == b { vec![1] } else { vec![0] };
                 self.stack.push(result);
                 Ok(())
@@ -106,42 +143,39 @@ impl Engine {
     fn op_sha256(&mut self) -> Result<(), String> {
         match self.stack.pop() {
             Some(data) => {
-                let data = hex::encode(data);
-                self.stack.push(sha256(&data.as_bytes()));
+                self.stack.push(sha256(&data));
                 Ok(())
             },
             None => Err(String::from("OP_SHA256: missing value on stack."))
         }
     }
 
-    fn pop_i32(&mut self) -> Option<i32> {
-        let mut a = self.stack.pop()?;
-
-        // the bytes might not have a length of 4, so we reverse the byte order, and pad the vector
-        // to a 4 bytes length. That byte array can then be parsed as a little endian i32.
-        a.reverse();
-        a.resize(4, 0);
-
-        Some(i32::from_le_bytes(a.try_into().unwrap()))
+    fn op_hash160(&mut self) -> Result<(), String> {
+        match self.stack.pop() {
+            Some(data) => {
+                self.stack.push(hash160(&data));
+                Ok(())
+            },
+            None => Err(String::from("OP_HASH160: missing value on stack."))
+        }
     }
 }
 
-fn smallest_i32_bytes(n: i32) -> Vec<u8> {
-    let [a, b, c, d]: [u8; 4] = n.to_be_bytes();
-
-    if (n as u32 & 0xffffff00) == 0 {
-        return vec![d]
+/// Reads a number operand, see `script::script_number` for the encoding.
+fn read_number(bytes: &[u8]) -> Result<i64, String> {
+    if bytes.len() > MAX_NUMBER_SIZE {
+        return Err(String::from("script number overflow."))
     };
 
-    if (n as u32 & 0xffff0000) == 0 {
-        return vec![c, d]
+    let Some((last, _)) = bytes.split_last() else {
+        return Ok(0)
     };
 
-    if (n as u32 & 0xff000000) == 0 {
-        return vec![b, c, d]
-    };
+    let abs = bytes.iter().rev()
+        .fold(0i64, |n, byte| n << 8 | *byte as i64)
+        & !(0x80 << (8 * (bytes.len() - 1)));
 
-    vec![a, b, c, d]
+    Ok(if last & 0x80 != 0 { -abs } else { abs })
 }
 
 #[cfg(test)]
@@ -152,50 +186,111 @@ mod test {
     #[test]
     fn test_math_puzzle_success() {
         // 4 4 ADD 8 EQUAL
-        let script = hex::decode("0104010493010887").unwrap();
-        assert_eq!(Ok(true), new().execute(script))
+        let script = Script::from_hex("0104010493010887").unwrap();
+        assert_eq!(Ok(true), new().execute(&script))
     }
 
     #[test]
     fn test_math_puzzle_failure() {
         // 4 1 ADD 8 EQUAL
-        let script = hex::decode("0104010193010887").unwrap();
-        assert_eq!(Ok(false), new().execute(script))
+        let script = Script::from_hex("0104010193010887").unwrap();
+        assert_eq!(Ok(false), new().execute(&script))
     }
 
     #[test]
     fn test_math_puzzle_add_missing_stack_value() {
         // 4 ADD 8 EQUAL
-        let script = hex::decode("010493010887").unwrap();
-        assert_eq!(Err(String::from("OP_ADD: missing values on stack.")), new().execute(script))
+        let script = Script::from_hex("010493010887").unwrap();
+        assert_eq!(Err(String::from("OP_ADD: missing values on stack.")), new().execute(&script))
     }
 
     #[test]
     fn test_math_puzzle_equal_missing_stack_value() {
         // 4 4 ADD EQUAL
-        let script = hex::decode("010401049387").unwrap();
-        assert_eq!(Err(String::from("OP_EQUAL: missing values on stack.")), new().execute(script))
+        let script = Script::from_hex("010401049387").unwrap();
+        assert_eq!(Err(String::from("OP_EQUAL: missing values on stack.")), new().execute(&script))
     }
 
     #[test]
     fn test_hash_puzzle_success() {
-        // 72636f696e OP_SHA256 e49dc62d36294343898b5a0b29335600c1106b70a2827371fe1321013d764a85 OP_EQUAL
-        let script = hex::decode("0572636f696ea820e49dc62d36294343898b5a0b29335600c1106b70a2827371fe1321013d764a8587").unwrap();
-        assert_eq!(Ok(true), new().execute(script));
+        // 72636f696e OP_SHA256 660e4502ce8f393eb5d5710febc339a58778bce175e4647ce50f8639786d132a OP_EQUAL
+        let script = Script::from_hex("0572636f696ea820660e4502ce8f393eb5d5710febc339a58778bce175e4647ce50f8639786d132a87").unwrap();
+        assert_eq!(Ok(true), new().execute(&script));
     }
 
     #[test]
     fn test_hash_puzzle_failure() {
-        // 0000000000 OP_SHA256 e49dc62d36294343898b5a0b29335600c1106b70a2827371fe1321013d764a85 OP_EQUAL
-        let script = hex::decode("050000000000a820e49dc62d36294343898b5a0b29335600c1106b70a2827371fe1321013d764a8587").unwrap();
-        assert_eq!(Ok(false), new().execute(script))
+        // 0000000000 OP_SHA256 660e4502ce8f393eb5d5710febc339a58778bce175e4647ce50f8639786d132a OP_EQUAL
+        let script = Script::from_hex("050000000000a820660e4502ce8f393eb5d5710febc339a58778bce175e4647ce50f8639786d132a87").unwrap();
+        assert_eq!(Ok(false), new().execute(&script))
     }
 
     #[test]
     fn test_sha256_missing_stack_value() {
-        // OP_SHA256 e49dc62d36294343898b5a0b29335600c1106b70a2827371fe1321013d764a85 OP_EQUAL
-        let script = hex::decode("a820e49dc62d36294343898b5a0b29335600c1106b70a2827371fe1321013d764a8587").unwrap();
-        assert_eq!(Err(String::from("OP_SHA256: missing value on stack.")), new().execute(script))
+        // OP_SHA256 660e4502ce8f393eb5d5710febc339a58778bce175e4647ce50f8639786d132a OP_EQUAL
+        let script = Script::from_hex("a820660e4502ce8f393eb5d5710febc339a58778bce175e4647ce50f8639786d132a87").unwrap();
+        assert_eq!(Err(String::from("OP_SHA256: missing value on stack.")), new().execute(&script))
+    }
+
+    #[test]
+    fn test_add_numbers() {
+        // -1 1 ADD 0 EQUAL
+        let script = Script::from_hex("4f51930087").unwrap();
+        assert_eq!(Ok(true), new().execute(&script));
+
+        // 2147483647 2147483647 ADD 4294967294 EQUAL: the result takes 5 bytes.
+        let script = Script::from_hex("04ffffff7f04ffffff7f9305feffffff0087").unwrap();
+        assert_eq!(Ok(true), new().execute(&script));
+
+        // 4294967294 1 ADD: the operand is too large.
+        let script = Script::from_hex("05feffffff005193").unwrap();
+        assert_eq!(Err(String::from("script number overflow.")), new().execute(&script));
+    }
+
+    #[test]
+    fn test_push_past_end_of_script() {
+        // 4 <push 3 bytes> 8
+        let script = Script::from_hex("0104030108").unwrap();
+        assert_eq!(Err(String::from("OP_PUSHBYTES: push past the end of the script.")), new().execute(&script))
+    }
+
+    #[test]
+    fn test_unsupported_opcode() {
+        // 1 OP_DUP
+        let script = Script::from_hex("5176").unwrap();
+        assert_eq!(Err(String::from("OP_DUP: not supported.")), new().execute(&script))
+    }
+
+    #[test]
+    fn test_verify_p2sh() {
+        // the redeem script is OP_1, anyone knowing it can spend the output.
+        let script_pub_key = Script::new_p2sh(&hash160(&[OP_1]));
+        let mut script_sig = Script::new();
+        script_sig.push_slice(&[OP_1]);
+        assert_eq!(Ok(true), new().verify(&script_sig, &script_pub_key));
+
+        let mut wrong_script_sig = Script::new();
+        wrong_script_sig.push_slice(&[OP_16]);
+        assert_eq!(Ok(false), new().verify(&wrong_script_sig, &script_pub_key));
+
+        // the redeem script 0 matches, but leaves false on the stack.
+        let script_pub_key = Script::new_p2sh(&hash160(&[OP_0]));
+        let mut script_sig = Script::new();
+        script_sig.push_slice(&[OP_0]);
+        assert_eq!(Ok(false), new().verify(&script_sig, &script_pub_key));
+    }
+
+    #[test]
+    fn test_verify_unverifiable() {
+        let mut script_sig = Script::new();
+        script_sig.push_slice(&[0xab; 72]);
+        script_sig.push_slice(&[0x02; 33]);
+
+        let p2pkh = Script::from_hex("76a914db4d1141d0048b1ed15839d0b7a4c488cd368b0e88ac").unwrap();
+        assert_eq!(Err(String::from("OP_DUP: not supported.")), new().verify(&script_sig, &p2pkh));
+
+        let p2wpkh = Script::new_witness_program(0, &[0xab; 20]);
+        assert!(new().verify(&Script::new(), &p2wpkh).is_err());
     }
 
     // #[test]
@@ -1,3 +1,495 @@
-mod opcodes;
+pub mod opcodes;
 
 pub mod engine;
+
+use std::fmt;
+use std::io::{Read, Write};
+
+use crate::encoding::{Decodable, Encodable, Error};
+use crate::script::opcodes::*;
+
+/// A script, as found in transaction inputs (scriptSig) and outputs (scriptPubKey).
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub struct Script(Vec<u8>);
+
+/// The standard kinds of output scripts, see bitcoin core's `TxoutType`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ScriptType {
+    /// <pubkey> OP_CHECKSIG
+    PubKey,
+    PubKeyHash,
+    ScriptHash,
+    /// OP_m <pubkey>... OP_n OP_CHECKMULTISIG, with at most 3 public keys.
+    Multisig,
+    /// OP_RETURN followed by data pushes: an unspendable output carrying data.
+    NullData,
+    WitnessV0KeyHash,
+    WitnessV0ScriptHash,
+    WitnessV1Taproot,
+    /// A witness program of a version or size without meaning yet.
+    WitnessUnknown,
+}
+
+/// A single step of a script: either data pushed onto the stack, or any other opcode.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Instruction<'a> {
+    PushBytes(&'a [u8]),
+    Op(u8),
+}
+
+impl Script {
+    pub fn new() -> Script {
+        Script(vec![])
+    }
+
+    pub fn from_hex(script_hex: &str) -> Result<Script, Error> {
+        let bytes = hex::decode(script_hex).map_err(|_| Error::InvalidHex)?;
+        Ok(Script(bytes))
+    }
+
+    /// Pay to public key hash: OP_DUP OP_HASH160 <hash> OP_EQUALVERIFY OP_CHECKSIG
+    pub fn new_p2pkh(pubkey_hash: &[u8]) -> Script {
+        let mut script = Script::new();
+        script.push_opcode(OP_DUP);
+        script.push_opcode(OP_HASH160);
+        script.push_slice(pubkey_hash);
+        script.push_opcode(OP_EQUALVERIFY);
+        script.push_opcode(OP_CHECKSIG);
+        script
+    }
+
+    /// Pay to script hash: OP_HASH160 <hash> OP_EQUAL
+    pub fn new_p2sh(script_hash: &[u8]) -> Script {
+        let mut script = Script::new();
+        script.push_opcode(OP_HASH160);
+        script.push_slice(script_hash);
+        script.push_opcode(OP_EQUAL);
+        script
+    }
+
+    /// A segwit output: the witness version (OP_0 to OP_16), then the witness program.
+    pub fn new_witness_program(version: u8, program: &[u8]) -> Script {
+        let mut script = Script::new();
+        script.push_int(version as i64);
+        script.push_slice(program);
+        script
+    }
+
+    pub fn push_opcode(&mut self, op: u8) {
+        self.0.push(op);
+    }
+
+    /// Pushes data with the smallest push opcode.
+    pub fn push_slice(&mut self, data: &[u8]) {
+        match data.len() {
+            0..=0x4b => self.0.push(data.len() as u8),
+            0x4c..=0xff => self.0.extend([OP_PUSHDATA1, data.len() as u8]),
+            0x100..=0xffff => {
+                self.0.push(OP_PUSHDATA2);
+                self.0.extend((data.len() as u16).to_le_bytes());
+            },
+            _ => {
+                self.0.push(OP_PUSHDATA4);
+                self.0.extend((data.len() as u32).to_le_bytes());
+            },
+        };
+
+        self.0.extend(data);
+    }
+
+    /// Pushes a number: OP_1NEGATE, OP_0 and OP_1 to OP_16 for small numbers, a script number
+    /// otherwise.
+    pub fn push_int(&mut self, n: i64) {
+        match n {
+            -1 => self.push_opcode(OP_1NEGATE),
+            0 => self.push_opcode(OP_0),
+            1..=16 => self.push_opcode(OP_1 + (n as u8) - 1),
+            _ => self.push_slice(&script_number(n)),
+        };
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.0
+    }
+
+    pub fn to_hex(&self) -> String {
+        hex::encode(&self.0)
+    }
+
+    pub fn instructions(&self) -> Instructions<'_> {
+        Instructions { data: &self.0 }
+    }
+
+    /// Whether the script only pushes data, as required of scriptSigs by the standardness policy.
+    pub fn is_push_only(&self) -> bool {
+        self.instructions().all(|instruction| match instruction {
+            Ok(Instruction::PushBytes(_)) => true,
+            Ok(Instruction::Op(op)) => op == OP_1NEGATE || (OP_1..=OP_16).contains(&op),
+            Err(_) => false,
+        })
+    }
+
+    /// The witness version and program of a segwit output: a version opcode (OP_0 to OP_16),
+    /// then a push of 2 to 40 bytes.
+    pub fn witness_program(&self) -> Option<(u8, &[u8])> {
+        let bytes = &self.0;
+
+        if bytes.len() < 4 || bytes.len() > 42 || bytes[1] as usize != bytes.len() - 2 {
+            return None
+        };
+
+        match bytes[0] {
+            OP_0 => Some((0, &bytes[2..])),
+            OP_1..=OP_16 => Some((bytes[0] - OP_1 + 1, &bytes[2..])),
+            _ => None,
+        }
+    }
+
+    /// The kind of the output script, or None if it isn't standard.
+    ///
+    /// https://github.com/bitcoin/bitcoin/blob/master/src/script/solver.cpp
+    pub fn script_type(&self) -> Option<ScriptType> {
+        let bytes = &self.0;
+
+        if let Some((version, program)) = self.witness_program() {
+            return match (version, program.len()) {
+                (0, 20) => Some(ScriptType::WitnessV0KeyHash),
+                (0, 32) => Some(ScriptType::WitnessV0ScriptHash),
+                (0, _) => None,
+                (1, 32) => Some(ScriptType::WitnessV1Taproot),
+                _ => Some(ScriptType::WitnessUnknown),
+            }
+        };
+
+        match bytes[..] {
+            [OP_DUP, OP_HASH160, 20, .., OP_EQUALVERIFY, OP_CHECKSIG] if bytes.len() == 25 => return Some(ScriptType::PubKeyHash),
+            [OP_HASH160, 20, .., OP_EQUAL] if bytes.len() == 23 => return Some(ScriptType::ScriptHash),
+            [33, .., OP_CHECKSIG] if bytes.len() == 35 => return Some(ScriptType::PubKey),
+            [65, .., OP_CHECKSIG] if bytes.len() == 67 => return Some(ScriptType::PubKey),
+            [OP_RETURN, ..] if Script(bytes[1..].to_vec()).is_push_only() => return Some(ScriptType::NullData),
+            _ => {},
+        };
+
+        let instructions = self.instructions().collect::<Result<Vec<_>, _>>().ok()?;
+        match instructions[..] {
+            [Instruction::Op(m), ref keys @ .., Instruction::Op(n), Instruction::Op(OP_CHECKMULTISIG)]
+                if (OP_1..=OP_16).contains(&m) && (OP_1..=OP_16).contains(&n) && m <= n
+                    && keys.len() == (n - OP_1 + 1) as usize && keys.len() <= 3
+                    && keys.iter().all(|key| matches!(key, Instruction::PushBytes(key) if key.len() == 33 || key.len() == 65)) => {
+                Some(ScriptType::Multisig)
+            },
+            _ => None,
+        }
+    }
+
+    /// The script without its OP_CODESEPARATORs, as signed by the legacy signature hash. Past a
+    /// push going beyond the end of the script, the bytes are kept as they are.
+    pub fn without_code_separators(&self) -> Script {
+        let mut script = vec![];
+        let mut instructions = self.instructions();
+        let mut start = 0;
+
+        while let Some(Ok(instruction)) = instructions.next() {
+            let end = self.0.len() - instructions.data.len();
+
+            if instruction != Instruction::Op(OP_CODESEPARATOR) {
+                script.extend(&self.0[start..end]);
+            };
+            start = end;
+        };
+
+        script.extend(&self.0[start..]);
+        Script(script)
+    }
+
+    /// The human readable representation of the script, e.g. "OP_DUP OP_HASH160 <hex> ...".
+    pub fn to_asm(&self) -> String {
+        let mut asm: Vec<String> = vec![];
+
+        for instruction in self.instructions() {
+            match instruction {
+                Ok(Instruction::PushBytes([])) => asm.push(String::from("0")),
+                Ok(Instruction::PushBytes(data)) => asm.push(hex::encode(data)),
+                Ok(Instruction::Op(op)) => asm.push(String::from(opcodes::name(op))),
+                Err(_) => {
+                    asm.push(String::from("[error]"));
+                    break
+                },
+            };
+        };
+
+        asm.join(" ")
+    }
+}
+
+impl From<Vec<u8>> for Script {
+    fn from(bytes: Vec<u8>) -> Script {
+        Script(bytes)
+    }
+}
+
+impl fmt::Display for Script {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.to_asm())
+    }
+}
+
+impl fmt::LowerHex for Script {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.to_hex())
+    }
+}
+
+impl Encodable for Script {
+    fn consensus_encode<W: Write + ?Sized>(&self, writer: &mut W) -> Result<usize, Error> {
+        self.0.consensus_encode(writer)
+    }
+}
+
+impl Decodable for Script {
+    fn consensus_decode<R: Read + ?Sized>(reader: &mut R) -> Result<Self, Error> {
+        Ok(Script(Vec::<u8>::consensus_decode(reader)?))
+    }
+}
+
+/// The minimal encoding of a number on the stack: little endian, with the highest bit of the last
+/// byte as the sign.
+pub fn script_number(n: i64) -> Vec<u8> {
+    let mut bytes = vec![];
+    let mut abs = n.unsigned_abs();
+
+    while abs > 0 {
+        bytes.push((abs & 0xff) as u8);
+        abs >>= 8;
+    };
+
+    // one more byte if the highest bit is already used by the number.
+    match bytes.last() {
+        Some(last) if last & 0x80 != 0 => bytes.push(if n < 0 { 0x80 } else { 0x00 }),
+        Some(_) if n < 0 => *bytes.last_mut().unwrap() |= 0x80,
+        _ => {},
+    };
+
+    bytes
+}
+
+/// Iterates over the instructions of a script. Yields an error, then stops, if a push goes past
+/// the end of the script.
+pub struct Instructions<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Iterator for Instructions<'a> {
+    type Item = Result<Instruction<'a>, String>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (&op, rest) = self.data.split_first()?;
+
+        let (size_len, size) = match op {
+            OP_0..=0x4b => (0, op as usize),
+            OP_PUSHDATA1 | OP_PUSHDATA2 | OP_PUSHDATA4 => {
+                let size_len = match op { OP_PUSHDATA1 => 1, OP_PUSHDATA2 => 2, _ => 4 };
+
+                if rest.len() < size_len {
+                    self.data = &[];
+                    return Some(Err(format!("{}: missing data size.", opcodes::name(op))))
+                };
+
+                let mut size = [0; 4];
+                size[..size_len].copy_from_slice(&rest[..size_len]);
+                (size_len, u32::from_le_bytes(size) as usize)
+            },
+            _ => {
+                self.data = rest;
+                return Some(Ok(Instruction::Op(op)))
+            },
+        };
+
+        let rest = &rest[size_len..];
+        if rest.len() < size {
+            self.data = &[];
+            return Some(Err(format!("{}: push past the end of the script.", opcodes::name(op))))
+        };
+
+        self.data = &rest[size..];
+        Some(Ok(Instruction::PushBytes(&rest[..size])))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::encoding;
+
+    #[test]
+    fn instructions_test() {
+        // OP_DUP OP_HASH160 <20 bytes> OP_EQUALVERIFY OP_CHECKSIG
+        let script = Script::from_hex("76a914db4d1141d0048b1ed15839d0b7a4c488cd368b0e88ac").unwrap();
+        let instructions: Vec<_> = script.instructions().map(|i| i.unwrap()).collect();
+
+        assert_eq!(
+            vec![
+                Instruction::Op(OP_DUP),
+                Instruction::Op(OP_HASH160),
+                Instruction::PushBytes(&hex::decode("db4d1141d0048b1ed15839d0b7a4c488cd368b0e").unwrap()),
+                Instruction::Op(OP_EQUALVERIFY),
+                Instruction::Op(OP_CHECKSIG),
+            ],
+            instructions
+        );
+    }
+
+    #[test]
+    fn instructions_pushdata_test() {
+        let script = Script::from_hex("004c02abcd4d0100ef4e01000000ff").unwrap();
+        let instructions: Vec<_> = script.instructions().map(|i| i.unwrap()).collect();
+
+        assert_eq!(
+            vec![
+                Instruction::PushBytes(&[]),
+                Instruction::PushBytes(&[0xab, 0xcd]),
+                Instruction::PushBytes(&[0xef]),
+                Instruction::PushBytes(&[0xff]),
+            ],
+            instructions
+        );
+    }
+
+    #[test]
+    fn instructions_truncated_push_test() {
+        let script = Script::from_hex("03abcd").unwrap();
+        let mut instructions = script.instructions();
+
+        assert_eq!(Some(Err(String::from("OP_PUSHBYTES: push past the end of the script."))), instructions.next());
+        assert_eq!(None, instructions.next());
+
+        let script = Script::from_hex("4d01").unwrap();
+        assert_eq!(
+            Some(Err(String::from("OP_PUSHDATA2: missing data size."))),
+            script.instructions().next()
+        );
+    }
+
+    #[test]
+    fn to_asm_test() {
+        let script = Script::from_hex("76a914db4d1141d0048b1ed15839d0b7a4c488cd368b0e88ac").unwrap();
+        assert_eq!(
+            "OP_DUP OP_HASH160 db4d1141d0048b1ed15839d0b7a4c488cd368b0e OP_EQUALVERIFY OP_CHECKSIG",
+            script.to_string()
+        );
+
+        let script = Script::from_hex("6a0003abcd").unwrap();
+        assert_eq!("OP_RETURN 0 [error]", script.to_asm());
+    }
+
+    #[test]
+    fn hex_test() {
+        let script_hex = "76a914db4d1141d0048b1ed15839d0b7a4c488cd368b0e88ac";
+        let script = Script::from_hex(script_hex).unwrap();
+
+        assert_eq!(25, script.len());
+        assert_eq!(script_hex, script.to_hex());
+        assert_eq!(script_hex, format!("{:x}", script));
+        assert_eq!(Err(Error::InvalidHex), Script::from_hex("76a"));
+    }
+
+    #[test]
+    fn standard_scripts_test() {
+        let hash = hex::decode("db4d1141d0048b1ed15839d0b7a4c488cd368b0e").unwrap();
+
+        assert_eq!("76a914db4d1141d0048b1ed15839d0b7a4c488cd368b0e88ac", Script::new_p2pkh(&hash).to_hex());
+        assert_eq!("a914db4d1141d0048b1ed15839d0b7a4c488cd368b0e87", Script::new_p2sh(&hash).to_hex());
+        assert_eq!("0014db4d1141d0048b1ed15839d0b7a4c488cd368b0e", Script::new_witness_program(0, &hash).to_hex());
+        assert_eq!("6002751e", Script::new_witness_program(16, &[0x75, 0x1e]).to_hex());
+    }
+
+    #[test]
+    fn script_type_test() {
+        let cases = [
+            ("76a914db4d1141d0048b1ed15839d0b7a4c488cd368b0e88ac", Some(ScriptType::PubKeyHash)),
+            ("a914db4d1141d0048b1ed15839d0b7a4c488cd368b0e87", Some(ScriptType::ScriptHash)),
+            ("0014db4d1141d0048b1ed15839d0b7a4c488cd368b0e", Some(ScriptType::WitnessV0KeyHash)),
+            ("00201863143c14c5166804bd19203356da136c985678cd4d27a1b8c6329604903262", Some(ScriptType::WitnessV0ScriptHash)),
+            ("512079be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798", Some(ScriptType::WitnessV1Taproot)),
+            ("6002751e", Some(ScriptType::WitnessUnknown)),
+            ("0010751e76e8199196d454941c45d1b3a323", None),
+            ("6a0b68656c6c6f20776f726c64", Some(ScriptType::NullData)),
+            ("6a", Some(ScriptType::NullData)),
+            ("6a76", None),
+            (
+                "2102b4632d08485ff1df2db55b9dafd23347d1c47a457072a1e87be26896549a8737ac",
+                Some(ScriptType::PubKey),
+            ),
+            (
+                "512102b4632d08485ff1df2db55b9dafd23347d1c47a457072a1e87be26896549a873751ae",
+                Some(ScriptType::Multisig),
+            ),
+            (
+                "522102b4632d08485ff1df2db55b9dafd23347d1c47a457072a1e87be26896549a873751ae",
+                None,
+            ),
+            ("51", None),
+            ("", None),
+        ];
+
+        for (script, expected) in cases {
+            assert_eq!(expected, Script::from_hex(script).unwrap().script_type(), "{}", script);
+        };
+    }
+
+    #[test]
+    fn is_push_only_test() {
+        assert!(Script::from_hex("004f51600161").unwrap().is_push_only());
+        assert!(!Script::from_hex("0076").unwrap().is_push_only());
+        assert!(!Script::from_hex("02ab").unwrap().is_push_only());
+    }
+
+    #[test]
+    fn without_code_separators_test() {
+        // the 0xab pushed as data stays, and so does the truncated push at the end.
+        let script = Script::from_hex("ab7601abab4c05ab").unwrap();
+        assert_eq!("7601ab4c05ab", script.without_code_separators().to_hex());
+    }
+
+    #[test]
+    fn push_test() {
+        let mut script = Script::new();
+        script.push_slice(&[0xab; 0x4c]);
+        script.push_slice(&[0xcd; 0x100]);
+        let instructions: Vec<_> = script.instructions().map(|i| i.unwrap()).collect();
+
+        assert_eq!(vec![Instruction::PushBytes(&[0xab; 0x4c]), Instruction::PushBytes(&[0xcd; 0x100])], instructions);
+        assert_eq!("4c4c", hex::encode(&script.as_bytes()[..2]));
+        assert_eq!("4d0001", hex::encode(&script.as_bytes()[78..81]));
+    }
+
+    #[test]
+    fn push_int_test() {
+        let cases: &[(i64, &str)] = &[
+            (-1, "4f"), (0, "00"), (1, "51"), (16, "60"), (17, "0111"), (127, "017f"), (128, "028000"),
+            (-128, "028080"), (255, "02ff00"), (256, "020001"), (-256, "020081"), (227931, "035b7a03"),
+        ];
+
+        for (n, expected) in cases {
+            let mut script = Script::new();
+            script.push_int(*n);
+            assert_eq!(*expected, script.to_hex(), "{}", n);
+        };
+    }
+
+    #[test]
+    fn encoding_test() {
+        let script = Script::from_hex("abcd").unwrap();
+
+        assert_eq!("02abcd", encoding::serialize_hex(&script));
+        assert_eq!(Ok(script), encoding::deserialize_hex("02abcd"));
+    }
+}
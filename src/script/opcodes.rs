@@ -1,12 +1,34 @@
 // https://en.bitcoin.it/wiki/Script
 
 // constants
+/// An empty array of bytes is pushed onto the stack.
+pub const OP_0: u8 = 0x00;
 /// The next byte contains the number of bytes to be pushed onto the stack.
 pub const OP_PUSHDATA1: u8 = 0x4c;
+/// The next two bytes contain the number of bytes to be pushed onto the stack in little endian order.
+pub const OP_PUSHDATA2: u8 = 0x4d;
+/// The next four bytes contain the number of bytes to be pushed onto the stack in little endian order.
+pub const OP_PUSHDATA4: u8 = 0x4e;
+/// The number -1 is pushed onto the stack.
+pub const OP_1NEGATE: u8 = 0x4f;
+/// The number 1 is pushed onto the stack.
+pub const OP_1: u8 = 0x51;
+/// The number 16 is pushed onto the stack.
+pub const OP_16: u8 = 0x60;
+
+// Flow control
+/// Marks transaction as invalid.
+pub const OP_RETURN: u8 = 0x6a;
+
+// Stack
+/// Duplicates the top stack item.
+pub const OP_DUP: u8 = 0x76;
 
 // Bitwise logic
 /// Returns 1 if the inputs are exactly equal, 0 otherwise.
 pub const OP_EQUAL: u8 = 0x87;
+/// Same as OP_EQUAL, but runs OP_VERIFY afterward.
+pub const OP_EQUALVERIFY: u8 = 0x88;
 
 // Arithmetic
 //
@@ -19,3 +41,65 @@ pub const OP_ADD: u8 = 0x93;
 // Crypto
 /// The input is hashed using SHA-256.
 pub const OP_SHA256: u8 = 0xa8;
+/// The input is hashed twice: first with SHA-256 and then with RIPEMD-160.
+pub const OP_HASH160: u8 = 0xa9;
+/// Signatures only sign the script after the most recently executed OP_CODESEPARATOR.
+pub const OP_CODESEPARATOR: u8 = 0xab;
+/// The entire transaction's outputs, inputs, and script are hashed. If the signature used by
+/// OP_CHECKSIG is a valid signature for this hash and public key, 1 is returned, 0 otherwise.
+pub const OP_CHECKSIG: u8 = 0xac;
+/// Compares the signatures against the public keys, 1 is returned if they all match, 0 otherwise.
+pub const OP_CHECKMULTISIG: u8 = 0xae;
+
+/// The name of an opcode, as used in the ASM representation of scripts.
+pub fn name(op: u8) -> &'static str {
+    const NAMES: &[&str] = &[
+        "OP_PUSHDATA1", "OP_PUSHDATA2", "OP_PUSHDATA4", "OP_1NEGATE", "OP_RESERVED", "OP_1",
+        "OP_2", "OP_3", "OP_4", "OP_5", "OP_6", "OP_7", "OP_8", "OP_9", "OP_10", "OP_11", "OP_12",
+        "OP_13", "OP_14", "OP_15", "OP_16", "OP_NOP", "OP_VER", "OP_IF", "OP_NOTIF", "OP_VERIF",
+        "OP_VERNOTIF", "OP_ELSE", "OP_ENDIF", "OP_VERIFY", "OP_RETURN", "OP_TOALTSTACK",
+        "OP_FROMALTSTACK", "OP_2DROP", "OP_2DUP", "OP_3DUP", "OP_2OVER", "OP_2ROT", "OP_2SWAP",
+        "OP_IFDUP", "OP_DEPTH", "OP_DROP", "OP_DUP", "OP_NIP", "OP_OVER", "OP_PICK", "OP_ROLL",
+        "OP_ROT", "OP_SWAP", "OP_TUCK", "OP_CAT", "OP_SUBSTR", "OP_LEFT", "OP_RIGHT", "OP_SIZE",
+        "OP_INVERT", "OP_AND", "OP_OR", "OP_XOR", "OP_EQUAL", "OP_EQUALVERIFY", "OP_RESERVED1",
+        "OP_RESERVED2", "OP_1ADD", "OP_1SUB", "OP_2MUL", "OP_2DIV", "OP_NEGATE", "OP_ABS",
+        "OP_NOT", "OP_0NOTEQUAL", "OP_ADD", "OP_SUB", "OP_MUL", "OP_DIV", "OP_MOD", "OP_LSHIFT",
+        "OP_RSHIFT", "OP_BOOLAND", "OP_BOOLOR", "OP_NUMEQUAL", "OP_NUMEQUALVERIFY",
+        "OP_NUMNOTEQUAL", "OP_LESSTHAN", "OP_GREATERTHAN", "OP_LESSTHANOREQUAL",
+        "OP_GREATERTHANOREQUAL", "OP_MIN", "OP_MAX", "OP_WITHIN", "OP_RIPEMD160", "OP_SHA1",
+        "OP_SHA256", "OP_HASH160", "OP_HASH256", "OP_CODESEPARATOR", "OP_CHECKSIG",
+        "OP_CHECKSIGVERIFY", "OP_CHECKMULTISIG", "OP_CHECKMULTISIGVERIFY", "OP_NOP1",
+        "OP_CHECKLOCKTIMEVERIFY", "OP_CHECKSEQUENCEVERIFY", "OP_NOP4", "OP_NOP5", "OP_NOP6",
+        "OP_NOP7", "OP_NOP8", "OP_NOP9", "OP_NOP10", "OP_CHECKSIGADD",
+    ];
+
+    match op {
+        OP_0 => "OP_0",
+        0x01..=0x4b => "OP_PUSHBYTES",
+        op => NAMES.get((op - OP_PUSHDATA1) as usize).unwrap_or(&"OP_UNKNOWN"),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn name_test() {
+        assert_eq!("OP_0", name(OP_0));
+        assert_eq!("OP_PUSHDATA1", name(OP_PUSHDATA1));
+        assert_eq!("OP_1", name(OP_1));
+        assert_eq!("OP_16", name(OP_16));
+        assert_eq!("OP_RETURN", name(OP_RETURN));
+        assert_eq!("OP_DUP", name(OP_DUP));
+        assert_eq!("OP_EQUALVERIFY", name(OP_EQUALVERIFY));
+        assert_eq!("OP_ADD", name(OP_ADD));
+        assert_eq!("OP_SHA256", name(OP_SHA256));
+        assert_eq!("OP_HASH160", name(OP_HASH160));
+        assert_eq!("OP_CHECKSIG", name(OP_CHECKSIG));
+        assert_eq!("OP_CHECKMULTISIG", name(OP_CHECKMULTISIG));
+        assert_eq!("OP_CHECKSIGADD", name(0xba));
+        assert_eq!("OP_UNKNOWN", name(0xbb));
+        assert_eq!("OP_UNKNOWN", name(0xff));
+    }
+}
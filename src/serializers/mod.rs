@@ -1 +1,2 @@
 pub mod block_serializer;
+pub mod template_serializer;
@@ -0,0 +1,66 @@
+use std::collections::HashMap;
+
+use crate::blocktemplate::BlockTemplate;
+use crate::difficulty;
+use crate::validation::MAX_BLOCK_WEIGHT;
+
+// The result of the getblocktemplate RPC.
+//
+// https://github.com/bitcoin/bips/blob/master/bip-0022.mediawiki
+// https://github.com/bitcoin/bips/blob/master/bip-0023.mediawiki
+
+pub fn to_json(template: &BlockTemplate) -> String {
+    serde_json::to_string(&serialize_to_json(template)).unwrap()
+}
+
+pub fn to_json_pretty(template: &BlockTemplate) -> String {
+    serde_json::to_string_pretty(&serialize_to_json(template)).unwrap()
+}
+
+fn serialize_to_json(template: &BlockTemplate) -> serde_json::Value {
+    let context = &template.context;
+
+    // the transactions depend on their parents by 1-based index in the template.
+    let positions: HashMap<_, _> = template.entries.iter()
+        .enumerate()
+        .map(|(i, entry)| (entry.txid, i + 1))
+        .collect();
+
+    let transactions: Vec<_> = template.entries.iter().map(|entry| {
+        let mut depends: Vec<_> = entry.tx.inputs.iter()
+            .filter_map(|txin| positions.get(&txin.txid).copied())
+            .collect();
+        depends.sort();
+        depends.dedup();
+
+        serde_json::json!({
+            "data": entry.tx.to_hex(),
+            "txid": hex::encode(entry.txid.to_be_bytes()),
+            "hash": hex::encode(entry.tx.wtxid().to_be_bytes()),
+            "depends": depends,
+            "fee": entry.fee,
+            "weight": entry.weight,
+        })
+    }).collect();
+
+    let mut json = serde_json::json!({
+        "version": template.version,
+        "previousblockhash": hex::encode(context.prev_block_hash.to_be_bytes()),
+        "transactions": transactions,
+        "coinbasevalue": template.coinbase_value,
+        "target": hex::encode(difficulty::bits_to_target(context.bits).to_be_bytes()),
+        "mintime": context.median_time_past + 1,
+        "mutable": ["time", "transactions", "prevblock"],
+        "noncerange": "00000000ffffffff",
+        "weightlimit": MAX_BLOCK_WEIGHT,
+        "curtime": context.now,
+        "bits": format!("{:08x}", context.bits),
+        "height": context.height,
+    });
+
+    if let Some(commitment) = &template.witness_commitment {
+        json["default_witness_commitment"] = serde_json::json!(commitment.to_hex());
+    };
+
+    json
+}
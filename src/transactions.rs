@@ -1,6 +1,28 @@
+use std::io::{Read, Write};
+
+use crate::encoding::{self, Decodable, Encodable, Error};
 use crate::hash::hash256;
+use crate::script::Script;
 use crate::u256;
 
+/// The highest input sequence signaling replaceability.
+pub const MAX_BIP125_RBF_SEQUENCE: u32 = 0xfffffffd;
+
+// https://en.bitcoin.it/wiki/OP_CHECKSIG#Hashtype_SIGHASH_ALL_(default)
+//
+// The last byte of a signature tells which parts of the transaction it signs. The signature hash
+// type is the lowest 5 bits, along with the ANYONECANPAY flag. Any other type signs like ALL.
+
+/// Signs every input and output.
+pub const SIGHASH_ALL: u32 = 0x01;
+/// Signs the inputs, but none of the outputs.
+pub const SIGHASH_NONE: u32 = 0x02;
+/// Signs the inputs, and only the output at the index of the signed input.
+pub const SIGHASH_SINGLE: u32 = 0x03;
+/// Only signs the signed input, others can be added.
+pub const SIGHASH_ANYONECANPAY: u32 = 0x80;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
 pub struct Transaction {
     pub version: u32,
     pub inputs: Vec<TxIn>,
@@ -9,55 +31,321 @@ pub struct Transaction {
 }
 
 impl Transaction {
+    pub fn from_bytes(bytes: &[u8]) -> Result<Transaction, Error> {
+        encoding::deserialize(bytes)
+    }
+
+    pub fn from_hex(tx_hex: &str) -> Result<Transaction, Error> {
+        encoding::deserialize_hex(tx_hex)
+    }
+
+    /// The transaction id: the hash of the transaction serialized without its witness data.
+    pub fn txid(&self) -> u256 {
+        u256::from_le_bytes(hash256(&self.to_bytes_without_witness()).try_into().unwrap())
+    }
+
+    /// The witness transaction id: the hash of the transaction serialized with its witness data.
+    /// Same as `txid()` for a transaction that doesn't have any witness.
+    pub fn wtxid(&self) -> u256 {
+        u256::from_le_bytes(hash256(&self.to_bytes()).try_into().unwrap())
+    }
+
     pub fn id(&self) -> String {
-        let data = self.to_bytes();
-        hex::encode(hash256(&data))
+        hex::encode(self.txid().to_le_bytes())
     }
 
-    pub fn to_bytes(&self) -> Vec<u8> {
-        let mut payload: Vec<u8> = Vec::new();
+    /// A coinbase transaction has a single input, which doesn't spend any previous output.
+    pub fn is_coinbase(&self) -> bool {
+        self.inputs.len() == 1 && self.inputs[0].outpoint() == OutPoint::null()
+    }
 
-        payload.extend(self.version.to_le_bytes());
+    /// Whether the transaction opts in to replace-by-fee: an input has a sequence below
+    /// 0xfffffffe, see BIP125.
+    pub fn signals_rbf(&self) -> bool {
+        self.inputs.iter().any(|txin| txin.sequence <= MAX_BIP125_RBF_SEQUENCE)
+    }
 
-        payload.push(self.inputs.len() as u8);
-        for txin in &self.inputs {
-            payload.extend(txin.to_bytes());
+    pub fn has_witness(&self) -> bool {
+        self.inputs.iter().any(|txin| !txin.witness.is_empty())
+    }
+
+    /// Size of the transaction serialized with its witness data.
+    pub fn size(&self) -> usize {
+        self.to_bytes().len()
+    }
+
+    // https://github.com/bitcoin/bips/blob/master/bip-0141.mediawiki#transaction-size-calculations
+    pub fn weight(&self) -> usize {
+        self.to_bytes_without_witness().len() * 3 + self.size()
+    }
+
+    pub fn vsize(&self) -> usize {
+        self.weight().div_ceil(4)
+    }
+
+    /// The legacy signature hash of the input at `input_index`, spending an output locked by
+    /// `script_code`. The signature of an input is made over its bytes, `to_le_bytes()`, with
+    /// `Keychain::sign`.
+    ///
+    /// The transaction is copied without the scriptSigs, except for the signed input which gets
+    /// `script_code` minus its OP_CODESEPARATORs. The parts `sighash_type` doesn't sign are
+    /// removed or blanked, then the copy is hashed along with `sighash_type`.
+    pub fn signature_hash(&self, input_index: usize, script_code: &Script, sighash_type: u32) -> u256 {
+        assert!(input_index < self.inputs.len(), "input {} of a transaction with {} inputs", input_index, self.inputs.len());
+
+        let base_type = sighash_type & 0x1f;
+        let anyone_can_pay = sighash_type & SIGHASH_ANYONECANPAY != 0;
+
+        // there is no output to sign. bitcoin core signs the number 1 instead of failing, which
+        // makes the signature valid for any transaction.
+        if base_type == SIGHASH_SINGLE && input_index >= self.outputs.len() {
+            return u256::ONE
+        };
+
+        let script_code = script_code.without_code_separators();
+
+        // with NONE and SINGLE, the other inputs can update their sequence.
+        let inputs = self.inputs.iter()
+            .enumerate()
+            .filter(|(i, _)| !anyone_can_pay || *i == input_index)
+            .map(|(i, txin)| TxIn {
+                txid: txin.txid,
+                vout: txin.vout,
+                script_sig: if i == input_index { script_code.clone() } else { Script::new() },
+                sequence: match base_type {
+                    SIGHASH_NONE | SIGHASH_SINGLE if i != input_index => 0,
+                    _ => txin.sequence,
+                },
+                witness: vec![],
+            })
+            .collect();
+
+        // with SINGLE, the outputs before the signed one are blanked, the ones after removed.
+        let outputs = match base_type {
+            SIGHASH_NONE => vec![],
+            SIGHASH_SINGLE => self.outputs[..=input_index].iter()
+                .enumerate()
+                .map(|(i, output)| match i == input_index {
+                    true => output.clone(),
+                    false => TxOut { value: u64::MAX, script_pub_key: Script::new() },
+                })
+                .collect(),
+            _ => self.outputs.clone(),
         };
 
-        payload.push(self.outputs.len() as u8);
-        for txout in &self.outputs {
-            payload.extend(txout.to_bytes());
+        let tx = Transaction { version: self.version, inputs, outputs, lock_time: self.lock_time };
+        let mut payload = tx.to_bytes_without_witness();
+        payload.extend(sighash_type.to_le_bytes());
+
+        u256::from_le_bytes(hash256(&payload).try_into().unwrap())
+    }
+
+    /// The BIP143 signature hash of the input at `input_index`, spending a segwit v0 output of
+    /// `value` satoshis locked by `script_code`. To sign several inputs, `sighash_cache` hashes the
+    /// parts they share once.
+    pub fn segwit_signature_hash(&self, input_index: usize, script_code: &Script, value: u64, sighash_type: u32) -> u256 {
+        self.sighash_cache().segwit_signature_hash(input_index, script_code, value, sighash_type)
+    }
+
+    // https://github.com/bitcoin/bips/blob/master/bip-0143.mediawiki#specification
+    pub fn sighash_cache(&self) -> SighashCache<'_> {
+        let mut prevouts = vec![];
+        let mut sequences = vec![];
+
+        for txin in &self.inputs {
+            prevouts.extend(txin.txid.to_le_bytes());
+            prevouts.extend(txin.vout.to_le_bytes());
+            sequences.extend(txin.sequence.to_le_bytes());
         };
 
-        payload.extend(self.lock_time.to_le_bytes());
+        let outputs: Vec<u8> = self.outputs.iter().flat_map(|output| output.to_bytes()).collect();
+
+        SighashCache {
+            tx: self,
+            hash_prevouts: hash256(&prevouts).try_into().unwrap(),
+            hash_sequence: hash256(&sequences).try_into().unwrap(),
+            hash_outputs: hash256(&outputs).try_into().unwrap(),
+        }
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        encoding::serialize(self)
+    }
+
+    pub fn to_bytes_without_witness(&self) -> Vec<u8> {
+        let mut payload: Vec<u8> = Vec::new();
+        self.encode(&mut payload, false).unwrap();
         payload
     }
 
     pub fn to_hex(&self) -> String {
         hex::encode(self.to_bytes())
     }
+
+    // https://github.com/bitcoin/bips/blob/master/bip-0144.mediawiki
+    //
+    // Segwit transactions are serialized with a 0x00 marker where the input count should be,
+    // followed by a 0x01 flag. The witness of every input comes after the outputs.
+    fn encode<W: Write + ?Sized>(&self, writer: &mut W, include_witness: bool) -> Result<usize, Error> {
+        let mut size = self.version.consensus_encode(writer)?;
+
+        if include_witness {
+            size += 0x00u8.consensus_encode(writer)?;
+            size += 0x01u8.consensus_encode(writer)?;
+        };
+
+        size += self.inputs.consensus_encode(writer)?;
+        size += self.outputs.consensus_encode(writer)?;
+
+        if include_witness {
+            for txin in &self.inputs {
+                size += txin.witness.consensus_encode(writer)?;
+            };
+        };
+
+        size += self.lock_time.consensus_encode(writer)?;
+        Ok(size)
+    }
 }
 
+/// The hashes of every outpoint, sequence and output of a transaction. Without them, the BIP143
+/// signature hash of each input would hash the whole transaction again, so signing every input
+/// would be quadratic.
+pub struct SighashCache<'a> {
+    tx: &'a Transaction,
+    pub hash_prevouts: [u8; 32],
+    pub hash_sequence: [u8; 32],
+    pub hash_outputs: [u8; 32],
+}
+
+impl SighashCache<'_> {
+    /// Same as `Transaction::segwit_signature_hash`. Unlike the legacy signature hash, the parts
+    /// `sighash_type` doesn't sign are replaced by zeros, and SIGHASH_SINGLE without a matching
+    /// output signs no output instead of the number 1.
+    pub fn segwit_signature_hash(&self, input_index: usize, script_code: &Script, value: u64, sighash_type: u32) -> u256 {
+        let tx = self.tx;
+        assert!(input_index < tx.inputs.len(), "input {} of a transaction with {} inputs", input_index, tx.inputs.len());
+
+        let txin = &tx.inputs[input_index];
+        let base_type = sighash_type & 0x1f;
+        let anyone_can_pay = sighash_type & SIGHASH_ANYONECANPAY != 0;
+        let signs_every_output = base_type != SIGHASH_NONE && base_type != SIGHASH_SINGLE;
+
+        let hash_prevouts = if anyone_can_pay { [0; 32] } else { self.hash_prevouts };
+        let hash_sequence = if anyone_can_pay || !signs_every_output { [0; 32] } else { self.hash_sequence };
+
+        let hash_outputs = match tx.outputs.get(input_index) {
+            _ if signs_every_output => self.hash_outputs,
+            Some(output) if base_type == SIGHASH_SINGLE => hash256(&output.to_bytes()).try_into().unwrap(),
+            _ => [0; 32],
+        };
+
+        let mut payload = vec![];
+        payload.extend(tx.version.to_le_bytes());
+        payload.extend(hash_prevouts);
+        payload.extend(hash_sequence);
+        payload.extend(txin.txid.to_le_bytes());
+        payload.extend(txin.vout.to_le_bytes());
+        payload.extend(encoding::serialize(script_code));
+        payload.extend(value.to_le_bytes());
+        payload.extend(txin.sequence.to_le_bytes());
+        payload.extend(hash_outputs);
+        payload.extend(tx.lock_time.to_le_bytes());
+        payload.extend(sighash_type.to_le_bytes());
+
+        u256::from_le_bytes(hash256(&payload).try_into().unwrap())
+    }
+}
+
+impl Encodable for Transaction {
+    fn consensus_encode<W: Write + ?Sized>(&self, writer: &mut W) -> Result<usize, Error> {
+        self.encode(writer, self.has_witness())
+    }
+}
+
+impl Decodable for Transaction {
+    fn consensus_decode<R: Read + ?Sized>(reader: &mut R) -> Result<Self, Error> {
+        let version = u32::consensus_decode(reader)?;
+
+        let mut flags = 0;
+        let mut inputs = Vec::<TxIn>::consensus_decode(reader)?;
+        let mut outputs = vec![];
+
+        if inputs.is_empty() {
+            flags = u8::consensus_decode(reader)?;
+
+            // a 0x00 flag is the output count of a transaction with no inputs and no outputs.
+            if flags != 0 {
+                inputs = Vec::<TxIn>::consensus_decode(reader)?;
+                outputs = Vec::<TxOut>::consensus_decode(reader)?;
+            }
+        } else {
+            outputs = Vec::<TxOut>::consensus_decode(reader)?;
+        };
+
+        if flags & 1 != 0 {
+            flags ^= 1;
+
+            for txin in inputs.iter_mut() {
+                txin.witness = Vec::<Vec<u8>>::consensus_decode(reader)?;
+            };
+
+            if inputs.iter().all(|txin| txin.witness.is_empty()) {
+                return Err(Error::SuperfluousWitness)
+            };
+        };
+
+        if flags != 0 {
+            return Err(Error::UnknownTransactionFlags(flags))
+        };
+
+        let lock_time = u32::consensus_decode(reader)?;
+
+        Ok(Transaction { version, inputs, outputs, lock_time })
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
 pub struct TxIn {
     pub txid: u256,
     pub vout: u32,
-    pub script_sig: String,
+    pub script_sig: Script,
     pub sequence: u32,
+    /// The witness stack of the input. It isn't part of the input serialization: segwit
+    /// transactions serialize every witness after the outputs.
+    pub witness: Vec<Vec<u8>>,
+}
+
+/// A reference to a transaction output: the id of its transaction and its index in the outputs.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct OutPoint {
+    pub txid: u256,
+    pub vout: u32,
+}
+
+impl OutPoint {
+    /// The outpoint spent by coinbase inputs.
+    pub fn null() -> OutPoint {
+        OutPoint { txid: u256::new(0), vout: 0xffffffff }
+    }
 }
 
 impl TxIn {
-    pub fn to_bytes(&self) -> Vec<u8> {
-        let mut payload: Vec<u8> = Vec::new();
+    /// The output spent by this input.
+    pub fn outpoint(&self) -> OutPoint {
+        OutPoint { txid: self.txid, vout: self.vout }
+    }
 
-        payload.extend(self.txid.to_le_bytes());
-        payload.extend(self.vout.to_le_bytes());
+    pub fn from_bytes(bytes: &[u8]) -> Result<TxIn, Error> {
+        encoding::deserialize(bytes)
+    }
 
-        let script_sig = hex::decode(&self.script_sig).unwrap();
-        payload.push(script_sig.len() as u8);
-        payload.extend(script_sig);
+    pub fn from_hex(txin_hex: &str) -> Result<TxIn, Error> {
+        encoding::deserialize_hex(txin_hex)
+    }
 
-        payload.extend(self.sequence.to_le_bytes());
-        payload
+    pub fn to_bytes(&self) -> Vec<u8> {
+        encoding::serialize(self)
     }
 
     pub fn to_hex(&self) -> String {
@@ -65,21 +353,45 @@ impl TxIn {
     }
 }
 
+impl Encodable for TxIn {
+    fn consensus_encode<W: Write + ?Sized>(&self, writer: &mut W) -> Result<usize, Error> {
+        let mut size = self.txid.consensus_encode(writer)?;
+        size += self.vout.consensus_encode(writer)?;
+        size += self.script_sig.consensus_encode(writer)?;
+        size += self.sequence.consensus_encode(writer)?;
+        Ok(size)
+    }
+}
+
+impl Decodable for TxIn {
+    fn consensus_decode<R: Read + ?Sized>(reader: &mut R) -> Result<Self, Error> {
+        Ok(TxIn {
+            txid: u256::consensus_decode(reader)?,
+            vout: u32::consensus_decode(reader)?,
+            script_sig: Script::consensus_decode(reader)?,
+            sequence: u32::consensus_decode(reader)?,
+            witness: vec![],
+        })
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
 pub struct TxOut {
     pub value: u64,
-    pub script_pub_key: String,
+    pub script_pub_key: Script,
 }
 
 impl TxOut {
-    pub fn to_bytes(&self) -> Vec<u8> {
-        let mut payload: Vec<u8> = Vec::new();
+    pub fn from_bytes(bytes: &[u8]) -> Result<TxOut, Error> {
+        encoding::deserialize(bytes)
+    }
 
-        payload.extend(self.value.to_le_bytes());
+    pub fn from_hex(txout_hex: &str) -> Result<TxOut, Error> {
+        encoding::deserialize_hex(txout_hex)
+    }
 
-        let script_pub_key = hex::decode(&self.script_pub_key).unwrap();
-        payload.push(script_pub_key.len() as u8); // won't work for variable int
-        payload.extend(script_pub_key);
-        payload
+    pub fn to_bytes(&self) -> Vec<u8> {
+        encoding::serialize(self)
     }
 
     pub fn to_hex(&self) -> String {
@@ -87,14 +399,82 @@ impl TxOut {
     }
 }
 
+impl Encodable for TxOut {
+    fn consensus_encode<W: Write + ?Sized>(&self, writer: &mut W) -> Result<usize, Error> {
+        let mut size = self.value.consensus_encode(writer)?;
+        size += self.script_pub_key.consensus_encode(writer)?;
+        Ok(size)
+    }
+}
+
+impl Decodable for TxOut {
+    fn consensus_decode<R: Read + ?Sized>(reader: &mut R) -> Result<Self, Error> {
+        Ok(TxOut {
+            value: u64::consensus_decode(reader)?,
+            script_pub_key: Script::consensus_decode(reader)?,
+        })
+    }
+}
+
 #[cfg(test)]
-mod test {
+pub(crate) mod test {
     use super::*;
+    use crate::hash::hash160;
+    use crate::script::opcodes::OP_1;
+    use crate::validation::COIN;
+
+    // Transactions shared by the utxo, validation, mempool and orphanage tests.
+
+    pub(crate) fn coinbase(tag: &[u8]) -> Transaction {
+        Transaction {
+            version: 1,
+            inputs: vec![TxIn {
+                txid: u256::new(0),
+                vout: 0xffffffff,
+                script_sig: Script::from(tag.to_vec()),
+                sequence: 0xffffffff,
+                witness: vec![],
+            }],
+            outputs: vec![TxOut { value: 50 * COIN, script_pub_key: p2sh() }],
+            lock_time: 0,
+        }
+    }
+
+    pub(crate) fn spend(outpoints: &[OutPoint], values: &[u64]) -> Transaction {
+        Transaction {
+            version: 1,
+            inputs: outpoints.iter().map(|outpoint| TxIn {
+                txid: outpoint.txid,
+                vout: outpoint.vout,
+                script_sig: redeem(),
+                sequence: 0xffffffff,
+                witness: vec![],
+            }).collect(),
+            outputs: values.iter().map(|value| TxOut { value: *value, script_pub_key: p2sh() }).collect(),
+            lock_time: 0,
+        }
+    }
+
+    /// A P2SH output the script engine can verify: its redeem script is OP_1, anyone can spend it.
+    pub(crate) fn p2sh() -> Script {
+        Script::new_p2sh(&hash160(&[OP_1]))
+    }
+
+    /// The scriptSig spending a `p2sh` output, pushing its redeem script.
+    fn redeem() -> Script {
+        let mut script_sig = Script::new();
+        script_sig.push_slice(&[OP_1]);
+        script_sig
+    }
+
+    pub(crate) fn outpoint(tx: &Transaction, vout: u32) -> OutPoint {
+        OutPoint { txid: tx.txid(), vout }
+    }
 
     #[test]
     pub fn txin_to_hex_test() {
         let tx = test_transaction();
-        let txin = tx.inputs.get(0).unwrap();
+        let txin = tx.inputs.first().unwrap();
         let expected_hex = "7967a5185e907a25225574544c31f7b059c1a191d65b53dcc1554d339c4f9\
                             efc010000006a47304402206a2eb16b7b92051d0fa38c133e67684ed064ef\
                             fada1d7f925c842da401d4f22702201f196b10e6e4b4a9fff948e5c5d71ec\
@@ -107,7 +487,7 @@ mod test {
     #[test]
     pub fn txout_to_hex_test() {
         let tx = test_transaction();
-        let txout = tx.outputs.get(0).unwrap();
+        let txout = tx.outputs.first().unwrap();
         assert_eq!("4baf2100000000001976a914db4d1141d0048b1ed15839d0b7a4c488cd368b0e88ac", txout.to_hex())
     }
 
@@ -139,6 +519,339 @@ mod test {
         assert_eq!(hex::encode(expected_txid), tx.id());
     }
 
+    #[test]
+    pub fn is_coinbase_test() {
+        let tx = test_transaction();
+        assert!(!tx.is_coinbase());
+        assert_eq!(OutPoint { txid: tx.inputs[0].txid, vout: 1 }, tx.inputs[0].outpoint());
+
+        let coinbase = &crate::block::genesis().transactions[0];
+        assert!(coinbase.is_coinbase());
+    }
+
+    #[test]
+    pub fn transaction_with_large_script_sig_test() {
+        // transaction 4126bdf1fcc2f215e6df00e283bb4808ab0cc890028c25f2912335da856fc03b from mainnet
+        // block 000000000000000000000c835b2adcaedc20fdf6ee440009c249452c726dafae. Its input spends
+        // a 5-of-6 P2SH multisig, so the scriptSig is 356 bytes long.
+        let script_sig_hex = "00483045022100d96d8d09402614caf8993b202aa4ceed62a75a6e04227e0f70\
+                              f7454660ba205a02202686327e81e210bda2e83ace8433da86f10fd61424ea7c\
+                              b71d398903ee92fa4501483045022100fb5c465876b485132539ee1b256e9362\
+                              10ab50c2a8b2116510950f3e35fd246302206d93a7132e99f2aa8c13aa364647\
+                              05cf7e0d074217cfccf856eff011cfee5f0c014ccf522102445fddb0aa691ca2\
+                              6d4bb6f44da84e0de7c2401993070b2680597871f906ec8a21027923bb570a79\
+                              25adf1aa2d22832c07b8fc725bcce4455d8955e5062fdf13558621033932fa60\
+                              575e5730eb600df2c81379c558e7820e59e068bb7bca07fd5dbebd4921034484\
+                              c25ea5bad8883c08f3e3aa3844dbf6e7ef94a5a628f65b8e4ae5b4609ac22103\
+                              8e67ce21c939626ca4293df0521823e4c4984864368a13ed6cf95aadc5387b98\
+                              2103bf6919bc7521d9b4a93264fae7d79cf9782f50accab6d04c54d978f5c312\
+                              b61556ae";
+
+        let tx = Transaction {
+            version: 1,
+            inputs: vec![
+                TxIn {
+                    txid: u256::from_le_bytes(
+                        hex::decode("c49f179bd4444c7486419ada8ebba9300409da5d59005f397ecb404562fdd439")
+                            .unwrap()
+                            .try_into()
+                            .unwrap()
+                    ),
+                    vout: 0,
+                    script_sig: Script::from_hex(script_sig_hex).unwrap(),
+                    sequence: 0xffffffff,
+                    witness: vec![],
+                }
+            ],
+            outputs: vec![
+                TxOut {
+                    value: 930276,
+                    script_pub_key: Script::from_hex("76a9143a7ef6ed89d7ca79fb52f1e25a567c2a031a964788ac").unwrap(),
+                },
+                TxOut {
+                    value: 54556562,
+                    script_pub_key: Script::from_hex("a9145b7d9bccc72a7e3bdfdf21a71467b7501e5137e187").unwrap(),
+                },
+            ],
+            lock_time: 0,
+        };
+
+        // the script length is written as a 3 bytes CompactSize: 0xfd 0x0164.
+        let expected_hex = format!(
+            "0100000001c49f179bd4444c7486419ada8ebba9300409da5d59005f397ecb404562fdd43900000000\
+             fd6401{}ffffffff02e4310e00000000001976a9143a7ef6ed89d7ca79fb52f1e25a567c2a031a9647\
+             88ac927740030000000017a9145b7d9bccc72a7e3bdfdf21a71467b7501e5137e18700000000",
+            script_sig_hex
+        );
+        assert_eq!(expected_hex, tx.to_hex());
+
+        let expected_txid: Vec<u8> = hex::decode("4126bdf1fcc2f215e6df00e283bb4808ab0cc890028c25f2912335da856fc03b")
+            .unwrap()
+            .into_iter()
+            .rev()
+            .collect();
+
+        assert_eq!(hex::encode(expected_txid), tx.id());
+    }
+
+    #[test]
+    pub fn transaction_with_many_inputs_test() {
+        // transaction cbf820e4508038797d8844ee7affc901450682a0caba871e3a87844a69228fde from mainnet
+        // block 000000000000000000000c835b2adcaedc20fdf6ee440009c249452c726dafae, with 386 inputs.
+        let expected_hex = include_str!("../test_data/tx_cbf820e4508038797d8844ee7affc901450682a0caba871e3a87844a69228fde.hex");
+        let tx = Transaction::from_hex(expected_hex).unwrap();
+
+        assert_eq!(386, tx.inputs.len());
+        assert_eq!("fd8201", hex::encode(&tx.to_bytes()[4..7]));
+        assert_eq!(expected_hex, tx.to_hex());
+
+        let expected_txid: Vec<u8> = hex::decode("cbf820e4508038797d8844ee7affc901450682a0caba871e3a87844a69228fde")
+            .unwrap()
+            .into_iter()
+            .rev()
+            .collect();
+
+        assert_eq!(hex::encode(expected_txid), tx.id());
+    }
+
+    #[test]
+    pub fn txin_from_hex_test() {
+        let tx = test_transaction();
+        let txin = tx.inputs.first().unwrap();
+
+        assert_eq!(Ok(txin.clone()), TxIn::from_hex(&txin.to_hex()));
+    }
+
+    #[test]
+    pub fn txout_from_hex_test() {
+        let txout = TxOut::from_hex("4baf2100000000001976a914db4d1141d0048b1ed15839d0b7a4c488cd368b0e88ac").unwrap();
+
+        assert_eq!(2207563, txout.value);
+        assert_eq!("76a914db4d1141d0048b1ed15839d0b7a4c488cd368b0e88ac", txout.script_pub_key.to_hex());
+    }
+
+    #[test]
+    pub fn transaction_from_hex_test() {
+        let tx = test_transaction();
+        assert_eq!(Ok(tx.clone()), Transaction::from_hex(&tx.to_hex()));
+        assert_eq!(Ok(tx.clone()), Transaction::from_bytes(&tx.to_bytes()));
+    }
+
+    #[test]
+    pub fn transaction_from_hex_truncated_test() {
+        let tx_hex = test_transaction().to_hex();
+
+        for end in (0..tx_hex.len()).step_by(2) {
+            assert_eq!(Err(Error::UnexpectedEnd), Transaction::from_hex(&tx_hex[..end]));
+        }
+    }
+
+    #[test]
+    pub fn transaction_from_hex_malformed_test() {
+        let tx_hex = test_transaction().to_hex();
+
+        assert_eq!(Err(Error::TrailingBytes(1)), Transaction::from_hex(&format!("{}00", tx_hex)));
+        assert_eq!(Err(Error::InvalidHex), Transaction::from_hex(&tx_hex[1..]));
+        assert_eq!(Err(Error::InvalidHex), Transaction::from_hex("zz"));
+
+        // input count written as a 3 bytes CompactSize.
+        let non_canonical = format!("{}fd0100{}", &tx_hex[..8], &tx_hex[10..]);
+        assert_eq!(Err(Error::NonCanonicalCompactSize), Transaction::from_hex(&non_canonical));
+    }
+
+    #[test]
+    pub fn segwit_transaction_test() {
+        // transaction f5864806e3565c34d1b41e716f72609d00b55ea5eac5b924c9719a842ef42206, spending a
+        // P2WPKH output.
+        let tx_hex = "02000000000101595895ea20179de87052b4046dfe6fd515860505d6511a9004cf12a1f93c\
+                      ac7c0100000000ffffffff01deb807000000000017a9140f3444e271620c736808aa7b33e3\
+                      70bd87cb5a078702483045022100fb60dad8df4af2841adc0346638c16d0b8035f5e3f3753\
+                      b88db122e70c79f9370220756e6633b17fd2710e626347d28d60b0a2d6cbb41de51740644b\
+                      9fb3ba7751040121028fa937ca8cba2197a37c007176ed8941055d3bcb8627d085e94553e6\
+                      2f057dcc00000000";
+
+        let tx = Transaction::from_hex(tx_hex).unwrap();
+
+        assert!(tx.has_witness());
+        assert_eq!(2, tx.inputs[0].witness.len());
+        assert_eq!(
+            "028fa937ca8cba2197a37c007176ed8941055d3bcb8627d085e94553e62f057dcc",
+            hex::encode(&tx.inputs[0].witness[1])
+        );
+        assert_eq!(tx_hex, tx.to_hex());
+
+        assert_eq!(
+            u256::from_str_radix("f5864806e3565c34d1b41e716f72609d00b55ea5eac5b924c9719a842ef42206", 16).unwrap(),
+            tx.txid()
+        );
+        assert_eq!(
+            u256::from_str_radix("80b7d8a82d5d5bf92905b06f2014dd699e03837ca172e3a59d51426ebbe3e7f5", 16).unwrap(),
+            tx.wtxid()
+        );
+
+        assert_eq!(193, tx.size());
+        assert_eq!(442, tx.weight());
+        assert_eq!(111, tx.vsize());
+    }
+
+    #[test]
+    pub fn segwit_transaction_multiple_witnesses_test() {
+        // segwit transaction from Liquid integration tests, spending two P2SH-P2WSH multisig
+        // outputs.
+        let tx_hex = "01000000000102ff34f95a672bb6a4f6ff4a7e90fa8c7b3be7e70ffc39bc99be3bda67942e836c00000000\
+                      23220020cde476664d3fa347b8d54ef3aee33dcb686a65ced2b5207cbf4ec5eda6b9b46e4f414d4c934ad8\
+                      1d330314e888888e3bd22c7dde8aac2ca9227b30d7c40093248af7812201000000232200200af6f6a071a6\
+                      9d5417e592ed99d256ddfd8b3b2238ac73f5da1b06fc0b2e79d54f414d4c0ba0c8f505000000001976a914\
+                      dcb5898d9036afad9209e6ff0086772795b1441088ac033c0f000000000017a914889f8c10ff2bd4bb9dab\
+                      b68c5c0d700a46925e6c87033c0f000000000017a914889f8c10ff2bd4bb9dabb68c5c0d700a46925e6c87\
+                      033c0f000000000017a914889f8c10ff2bd4bb9dabb68c5c0d700a46925e6c87033c0f000000000017a914\
+                      889f8c10ff2bd4bb9dabb68c5c0d700a46925e6c87033c0f000000000017a914889f8c10ff2bd4bb9dabb6\
+                      8c5c0d700a46925e6c87033c0f000000000017a914889f8c10ff2bd4bb9dabb68c5c0d700a46925e6c8703\
+                      3c0f000000000017a914889f8c10ff2bd4bb9dabb68c5c0d700a46925e6c87033c0f000000000017a91488\
+                      9f8c10ff2bd4bb9dabb68c5c0d700a46925e6c87033c0f000000000017a914889f8c10ff2bd4bb9dabb68c\
+                      5c0d700a46925e6c87033c0f000000000017a914889f8c10ff2bd4bb9dabb68c5c0d700a46925e6c870500\
+                      47304402200380b8663e727d7e8d773530ef85d5f82c0b067c97ae927800a0876a1f01d8e2022021ee611e\
+                      f6507dfd217add2cd60a8aea3cbcfec034da0bebf3312d19577b8c290147304402207bd9943ce1c2c5547b\
+                      120683fd05d78d23d73be1a5b5a2074ff586b9c853ed4202202881dcf435088d663c9af7b23efb3c03b9db\
+                      c0c899b247aa94a74d9b4b3c84f501483045022100ba12bba745af3f18f6e56be70f8382ca8e107d1ed5ce\
+                      aa3e8c360d5ecf78886f022069b38ebaac8fe6a6b97b497cbbb115f3176f7213540bef08f9292e5a72de52\
+                      de01695321023c9cd9c6950ffee24772be948a45dc5ef1986271e46b686cb52007bac214395a2102756e27\
+                      cb004af05a6e9faed81fd68ff69959e3c64ac8c9f6cd0e08fd0ad0e75d2103fa40da236bd82202a985a910\
+                      4e851080b5940812685769202a3b43e4a8b13e6a53ae050048304502210098b9687b81d725a7970d1eee91\
+                      ff6b89bc9832c2e0e3fb0d10eec143930b006f02206f77ce19dc58ecbfef9221f81daad90bb4f468df3912\
+                      12abc4f084fe2cc9bdef01483045022100e5479f81a3ad564103da5e2ec8e12f61f3ac8d312ab68763c1dd\
+                      d7bae94c20610220789b81b7220b27b681b1b2e87198897376ba9d033bc387f084c8b8310c8539c2014830\
+                      45022100aa1cc48a2d256c0e556616444cc08ae4959d464e5ffff2ae09e3550bdab6ce9f02207192d5e332\
+                      9a56ba7b1ead724634d104f1c3f8749fe6081e6233aee3e855817a016953210260de9cc68658c61af984e3\
+                      ab0281d17cfca1cc035966d335f474932d5e6c5422210355fbb768ce3ce39360277345dbb5f376e706459e\
+                      5a2b5e0e09a535e61690647021023222ceec58b94bd25925dd9743dae6b928737491bd940fc5dd7c6f5d5f\
+                      2adc1e53ae00000000";
+
+        let tx = Transaction::from_hex(tx_hex).unwrap();
+
+        assert_eq!(5, tx.inputs[0].witness.len());
+        assert_eq!(5, tx.inputs[1].witness.len());
+        assert_eq!(tx_hex, tx.to_hex());
+
+        assert_eq!(
+            u256::from_str_radix("9652aa62b0e748caeec40c4cb7bc17c6792435cc3dfe447dd1ca24f912a1c6ec", 16).unwrap(),
+            tx.txid()
+        );
+        assert_eq!(
+            u256::from_str_radix("d6ac4a5e61657c4c604dcde855a1db74ec6b3e54f32695d72c5e11c7761ea1b4", 16).unwrap(),
+            tx.wtxid()
+        );
+        assert_eq!(2718, tx.weight());
+    }
+
+    #[test]
+    pub fn transaction_without_witness_ids_test() {
+        let tx = test_transaction();
+
+        assert!(!tx.has_witness());
+        assert_eq!(tx.txid(), tx.wtxid());
+        assert_eq!(tx.to_bytes(), tx.to_bytes_without_witness());
+        assert_eq!(tx.size() * 4, tx.weight());
+        assert_eq!(tx.size(), tx.vsize());
+    }
+
+    #[test]
+    pub fn segwit_transaction_malformed_test() {
+        let mut tx = test_transaction();
+        tx.inputs[0].witness = vec![vec![0xab]];
+        let tx_hex = tx.to_hex();
+
+        // witness flag set, but the input has an empty witness.
+        let superfluous = tx_hex.replace("0101ab00000000", "0000000000");
+        assert_eq!(Err(Error::SuperfluousWitness), Transaction::from_hex(&superfluous));
+
+        let unknown_flags = format!("{}03{}", &tx_hex[..10], &tx_hex[12..]);
+        assert_eq!(Err(Error::UnknownTransactionFlags(0x02)), Transaction::from_hex(&unknown_flags));
+
+        // no inputs and no outputs: the flag byte is read as the output count.
+        let empty = Transaction::from_hex("01000000000000000000").unwrap();
+        assert!(empty.inputs.is_empty() && empty.outputs.is_empty());
+    }
+
+    #[test]
+    pub fn signature_hash_test() {
+        // vectors from bitcoin core's src/test/data/sighash.json: the transaction, the script
+        // code, the input index, the hash type, and the expected hash, as displayed by core.
+        let vectors: &[(&str, &str, usize, i32, &str)] = &[
+            ("b7877f82019c832707a60cf14fba44cfa254d787501fdd676bd58c744f6e951dbba0b3b77f0200000009ac515263ac53525300a5a36e500148f89c0500000000085265ac6a6a65acab00000000", "6563", 0, -1785108415, "cb6e4322955af12eb29613c70e1a00ddbb559c887ba844df0bcdebed736dffbd"),
+            ("e3cdbfb4014d90ae6a4401e85f7ac717adc2c035858bf6ff48979dd399d155bce1f150daea0300000002ac51a67a0d39017f6c71040000000005535200535200000000", "", 0, -1899950911, "c1c7df8206e661d593f6455db1d61a364a249407f88e99ecad05346e495b38d7"),
+            ("2f7353dd02e395b0a4d16da0f7472db618857cd3de5b9e2789232952a9b154d249102245fd030000000151617fd88f103280b85b0a198198e438e7cab1a4c92ba58409709997cc7a65a619eb9eec3c0200000003636aabffffffff0397481c0200000000045300636a0dc97803000000000009d389030000000003ac6a53134007bb", "0000536552526a", 0, -1912746174, "30c4cd4bd6b291f7e9489cc4b4440a083f93a7664ea1f93e77a9597dab8ded9c"),
+            ("32fa0b0804e6ea101e137665a041cc2350b794e59bf42d9b09088b01cde806ec1bbea077df0200000008515153650000006506a11c55904258fa418e57b88b12724b81153260d3f4c9f080439789a391ab147aabb0fa0000000007000052ac51ab510986f2a15c0d5e05d20dc876dd2dafa435276d53da7b47c393f20900e55f163b97ce0b800000000008ab526a520065636a8087df7d4d9c985fb42308fb09dce704650719140aa6050e8955fa5d2ea46b464a333f870000000009636300636a6565006affffffff01994a0d040000000002536500000000", "516563530065", 2, -163068286, "f58637277d2bc42e18358dc55f7e87e7043f5e33f4ce1fc974e715ef0d3d1c2a"),
+            ("6f62138301436f33a00b84a26a0457ccbfc0f82403288b9cbae39986b34357cb2ff9b889b302000000045253655335a7ff6701bac9960400000000086552ab656352635200000000", "6aac51", 0, 1444414211, "502a2435fd02898d2ff3ab08a3c19078414b32ec9b73d64a944834efc9dae10c"),
+            ("ae23424d040cd884ebfb9a815d8f17176980ab8015285e03fdde899449f4ae71e04275e9a80100000007ab006553530053ffffffff018e06db6af519dadc5280c07791c0fd33251500955e43fe4ac747a4df5c54df020000000251ac330e977c0fec6149a1768e0d312fdb53ed9953a3737d7b5d06aad4d86e9970346a4feeb5030000000951ab51ac6563ab526a67cabc431ee3d8111224d5ecdbb7d717aa8fe82ce4a63842c9bd1aa848f111910e5ae1eb0100000004ac515300bfb7e0d7048acddc030000000009636a5253636a655363a3428e040000000001525b99c6050000000004655265ab717e6e020000000000d99011eb", "ac6a6a516565", 1, -716251549, "b098eb9aff1bbd375c70a0cbb9497882ab51f3abfebbf4e1f8d74c0739dc7717"),
+            ("d3b7421e011f4de0f1cea9ba7458bf3486bee722519efab711a963fa8c100970cf7488b7bb0200000003525352dcd61b300148be5d05000000000000000000", "535251536aac536a", 0, -1960128125, "29aa6d2d752d3310eba20442770ad345b7f6a35f96161ede5f07b33e92053e2a"),
+            // unknown hash types, signing like ALL.
+            ("4db591ab018adcef5f4f3f2060e41f7829ce3a07ea41d681e8cb70a0e37685561e4767ac3b0000000005000052acabd280e63601ae6ef20000000000036a636326c908f7", "ac6a51526300630052", 0, 862877446, "355ccaf30697c9c5b966e619a554d3323d7494c3ea280a9b0dfb73f953f5c1cb"),
+            ("c33028b301d5093e1e8397270d75a0b009b2a6509a01861061ab022ca122a6ba935b8513320200000000ffffffff013bcf5a0500000000015200000000", "", 0, -513413204, "6b1459536f51482f5dbf42d7e561896557461e1e3b6bf67871e2b51faae2832c"),
+        ];
+
+        for (tx_hex, script_hex, input_index, sighash_type, expected) in vectors {
+            let tx = Transaction::from_hex(tx_hex).unwrap();
+            let script_code = Script::from_hex(script_hex).unwrap();
+            let hash = tx.signature_hash(*input_index, &script_code, *sighash_type as u32);
+
+            assert_eq!(*expected, hex::encode(hash.to_be_bytes()));
+        };
+    }
+
+    #[test]
+    pub fn signature_hash_single_bug_test() {
+        // the second input has no output to sign.
+        let mut tx = test_transaction();
+        tx.inputs.push(tx.inputs[0].clone());
+
+        assert_eq!(u256::ONE, tx.signature_hash(1, &Script::new(), SIGHASH_SINGLE));
+        assert_eq!(u256::ONE, tx.signature_hash(1, &Script::new(), SIGHASH_SINGLE | SIGHASH_ANYONECANPAY));
+        assert_ne!(u256::ONE, tx.signature_hash(0, &Script::new(), SIGHASH_SINGLE));
+    }
+
+    #[test]
+    pub fn segwit_signature_hash_test() {
+        // the native P2WPKH example of BIP143, signing the second input.
+        let tx = Transaction::from_hex("0100000002fff7f7881a8099afa6940d42d1e7f6362bec38171ea3edf433541db4e4ad969f0000000000eeffffffef51e1b804cc89d182d279655c3aa89e815b1b309fe287d9b2b55d57b90ec68a0100000000ffffffff02202cb206000000001976a9148280b37df378db99f66f85c95a783a76ac7a6d5988ac9093510d000000001976a9143bde42dbee7e4dbe6a21b2d50ce2f0167faa815988ac11000000").unwrap();
+        let script_code = Script::from_hex("76a9141d0f172a0ecb48aee1be1f2687d2963ae33f71a188ac").unwrap();
+        let cache = tx.sighash_cache();
+
+        assert_eq!("96b827c8483d4e9b96712b6713a7b68d6e8003a781feba36c31143470b4efd37", hex::encode(cache.hash_prevouts));
+        assert_eq!("52b0a642eea2fb7ae638c36f6252b6750293dbe574a806984b8e4d8548339a3b", hex::encode(cache.hash_sequence));
+        assert_eq!("863ef3e1a92afbfdb97f31ad0fc7683ee943e9abcf2501590ff8f6551f47e5e5", hex::encode(cache.hash_outputs));
+
+        let hash = cache.segwit_signature_hash(1, &script_code, 600_000_000, SIGHASH_ALL);
+        assert_eq!("c37af31116d1b27caf68aae9e3ac82f1477929014d5b917657d0eb49478cb670", hex::encode(hash.to_le_bytes()));
+
+        // the P2SH-P2WPKH example.
+        let tx = Transaction::from_hex("0100000001db6b1b20aa0fd7b23880be2ecbd4a98130974cf4748fb66092ac4d3ceb1a54770100000000feffffff02b8b4eb0b000000001976a914a457b684d7f0d539a46a45bbc043f35b59d0d96388ac0008af2f000000001976a914fd270b1ee6abcaea97fea7ad0402e8bd8ad6d77c88ac92040000").unwrap();
+        let script_code = Script::from_hex("76a91479091972186c449eb1ded22b78e40d009bdf008988ac").unwrap();
+        let hash = tx.segwit_signature_hash(0, &script_code, 1_000_000_000, SIGHASH_ALL);
+        assert_eq!("64f3b0f4dd2bb3aa1ce8566d220cc74dda9df97d8490cc81d89d735c92e59fb6", hex::encode(hash.to_le_bytes()));
+    }
+
+    #[test]
+    pub fn segwit_signature_hash_types_test() {
+        // the P2SH-P2WSH example of BIP143: a 6 of 6 multisig, signed with every hash type.
+        let tx = Transaction::from_hex("010000000136641869ca081e70f394c6948e8af409e18b619df2ed74aa106c1ca29787b96e0100000000ffffffff0200e9a435000000001976a914389ffce9cd9ae88dcc0631e88a821ffdbe9bfe2688acc0832f05000000001976a9147480a33f950689af511e6e84c138dbbd3c3ee41588ac00000000").unwrap();
+        let script_code = Script::from_hex("56210307b8ae49ac90a048e9b53357a2354b3334e9c8bee813ecb98e99a7e07e8c3ba32103b28f0c28bfab54554ae8c658ac5c3e0ce6e79ad336331f78c428dd43eea8449b21034b8113d703413d57761b8b9781957b8c0ac1dfe69f492580ca4195f50376ba4a21033400f6afecb833092a9a21cfdf1ed1376e58c5d1f47de74683123987e967a8f42103a6d48b1131e94ba04d9737d61acdaa1322008af9602b3b14862c07a1789aac162102d8b661b0b3302ee2f162b09e07a55ad5dfbe673a9f01d9f0c19617681024306b56ae").unwrap();
+        let cache = tx.sighash_cache();
+
+        let expected = [
+            (SIGHASH_ALL, "185c0be5263dce5b4bb50a047973c1b6272bfbd0103a89444597dc40b248ee7c"),
+            (SIGHASH_NONE, "e9733bc60ea13c95c6527066bb975a2ff29a925e80aa14c213f686cbae5d2f36"),
+            (SIGHASH_SINGLE, "1e1f1c303dc025bd664acb72e583e933fae4cff9148bf78c157d1e8f78530aea"),
+            (SIGHASH_ALL | SIGHASH_ANYONECANPAY, "2a67f03e63a6a422125878b40b82da593be8d4efaafe88ee528af6e5a9955c6e"),
+            (SIGHASH_NONE | SIGHASH_ANYONECANPAY, "781ba15f3779d5542ce8ecb5c18716733a5ee42a6f51488ec96154934e2c890a"),
+            (SIGHASH_SINGLE | SIGHASH_ANYONECANPAY, "511e8e52ed574121fc1b654970395502128263f62662e076dc6baf05c2e6a99b"),
+        ];
+
+        for (sighash_type, hash) in expected {
+            assert_eq!(hash, hex::encode(cache.segwit_signature_hash(0, &script_code, 987_654_321, sighash_type).to_le_bytes()));
+        };
+    }
+
     fn test_transaction() -> Transaction {
         // example input from https://learnmeabitcoin.com/technical/input
         let txid_hex = "7967a5185e907a25225574544c31f7b059c1a191d65b53dcc1554d339c4f9efc";
@@ -150,14 +863,15 @@ mod test {
         let txin = TxIn{
             txid: u256::from_le_bytes(hex::decode(txid_hex).unwrap().try_into().unwrap()),
             vout: 1,
-            script_sig: String::from(script_sig_hex),
+            script_sig: Script::from_hex(script_sig_hex).unwrap(),
             sequence: 0xffffffff,
+            witness: vec![],
         };
 
         // example output from https://learnmeabitcoin.com/technical/output
         let txout = TxOut{
             value: u64::from_le_bytes(hex::decode("4baf210000000000").unwrap().try_into().unwrap()),
-            script_pub_key: String::from("76a914db4d1141d0048b1ed15839d0b7a4c488cd368b0e88ac"),
+            script_pub_key: Script::from_hex("76a914db4d1141d0048b1ed15839d0b7a4c488cd368b0e88ac").unwrap(),
         };
 
         // transaction from https://learnmeabitcoin.com/explorer/transaction/c1b4e695098210a31fe02abffe9005cffc051bbe86ff33e173155bcbdc5821e3
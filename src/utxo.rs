@@ -0,0 +1,257 @@
+use std::collections::HashMap;
+use std::fmt;
+
+use crate::block::Block;
+use crate::script::Script;
+use crate::transactions::{OutPoint, Transaction, TxOut};
+
+// The set of unspent transaction outputs (UTXOs) of the active chain. Connecting a block spends
+// the outputs its inputs refer to and adds its new outputs. The spent outputs are returned as the
+// block's undo data, which is everything needed to disconnect the block later on.
+
+/// An unspent output, with the height of the block that created it.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Coin {
+    pub output: TxOut,
+    pub height: u32,
+    pub is_coinbase: bool,
+}
+
+/// The coins spent by a block, in the order of its (non coinbase) inputs.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct BlockUndo {
+    pub spent: Vec<Coin>,
+}
+
+#[derive(Default)]
+pub struct UtxoSet {
+    coins: HashMap<OutPoint, Coin>,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum Error {
+    /// An input spends an output that doesn't exist, or that is already spent.
+    MissingCoin(OutPoint),
+    /// The undo data doesn't match the inputs of the block being disconnected.
+    BadUndo,
+}
+
+pub fn new() -> UtxoSet {
+    UtxoSet::default()
+}
+
+impl UtxoSet {
+    pub fn get(&self, outpoint: &OutPoint) -> Option<&Coin> {
+        self.coins.get(outpoint)
+    }
+
+    pub fn contains(&self, outpoint: &OutPoint) -> bool {
+        self.coins.contains_key(outpoint)
+    }
+
+    pub fn len(&self) -> usize {
+        self.coins.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.coins.is_empty()
+    }
+
+    /// Every unspent output locked by `script_pub_key`, e.g. to find the coins of a wallet.
+    pub fn find_by_script(&self, script_pub_key: &Script) -> Vec<(OutPoint, &Coin)> {
+        self.coins.iter()
+            .filter(|(_, coin)| &coin.output.script_pub_key == script_pub_key)
+            .map(|(outpoint, coin)| (*outpoint, coin))
+            .collect()
+    }
+
+    /// Spends the inputs and adds the outputs of the block's transactions. Nothing is changed if
+    /// an input is missing.
+    pub fn connect_block(&mut self, block: &Block, height: u32) -> Result<BlockUndo, Error> {
+        let mut undo = BlockUndo::default();
+
+        for (i, tx) in block.transactions.iter().enumerate() {
+            if let Err(error) = self.connect_transaction(tx, height, &mut undo) {
+                self.disconnect_transactions(&block.transactions[..i], &mut undo).unwrap();
+                return Err(error)
+            };
+        };
+
+        Ok(undo)
+    }
+
+    /// Removes the outputs of the block's transactions, and restores the coins they spent.
+    pub fn disconnect_block(&mut self, block: &Block, undo: &BlockUndo) -> Result<(), Error> {
+        let inputs = block.transactions.iter()
+            .filter(|tx| !tx.is_coinbase())
+            .map(|tx| tx.inputs.len())
+            .sum::<usize>();
+
+        if inputs != undo.spent.len() {
+            return Err(Error::BadUndo)
+        };
+
+        self.disconnect_transactions(&block.transactions, &mut undo.clone())
+    }
+
+    fn connect_transaction(&mut self, tx: &Transaction, height: u32, undo: &mut BlockUndo) -> Result<(), Error> {
+        if !tx.is_coinbase() {
+            for (i, txin) in tx.inputs.iter().enumerate() {
+                match self.coins.remove(&txin.outpoint()) {
+                    Some(coin) => undo.spent.push(coin),
+                    None => {
+                        // put back what this transaction already spent.
+                        for txin in tx.inputs[..i].iter().rev() {
+                            self.coins.insert(txin.outpoint(), undo.spent.pop().unwrap());
+                        };
+                        return Err(Error::MissingCoin(txin.outpoint()))
+                    },
+                };
+            };
+        };
+
+        // like bitcoin core before BIP30, an output with the same outpoint is overwritten.
+        let txid = tx.txid();
+        for (vout, output) in tx.outputs.iter().enumerate() {
+            let coin = Coin { output: output.clone(), height, is_coinbase: tx.is_coinbase() };
+            self.coins.insert(OutPoint { txid, vout: vout as u32 }, coin);
+        };
+
+        Ok(())
+    }
+
+    /// Disconnects the transactions in reverse order, taking the spent coins from the end of
+    /// `undo`.
+    fn disconnect_transactions(&mut self, transactions: &[Transaction], undo: &mut BlockUndo) -> Result<(), Error> {
+        for tx in transactions.iter().rev() {
+            let txid = tx.txid();
+            for vout in 0..tx.outputs.len() {
+                self.coins.remove(&OutPoint { txid, vout: vout as u32 });
+            };
+
+            if tx.is_coinbase() {
+                continue
+            };
+
+            for txin in tx.inputs.iter().rev() {
+                let coin = undo.spent.pop().ok_or(Error::BadUndo)?;
+                self.coins.insert(txin.outpoint(), coin);
+            };
+        };
+
+        Ok(())
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::MissingCoin(outpoint) => write!(
+                f, "missing or spent output {}:{}", hex::encode(outpoint.txid.to_be_bytes()), outpoint.vout
+            ),
+            Error::BadUndo => write!(f, "undo data doesn't match the block"),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::block;
+    use crate::transactions::test::{coinbase, outpoint, p2sh, spend};
+    use crate::validation::COIN;
+
+    fn block(transactions: Vec<Transaction>) -> Block {
+        Block { header: block::genesis().header, transactions }
+    }
+
+    #[test]
+    fn connect_disconnect_test() {
+        let mut utxos = new();
+
+        let coinbase1 = coinbase(b"1");
+        let block1 = block(vec![coinbase1.clone()]);
+        let undo1 = utxos.connect_block(&block1, 1).unwrap();
+        assert_eq!(BlockUndo::default(), undo1);
+
+        // spends the coinbase, then an output of the same block.
+        let coinbase2 = coinbase(b"2");
+        let tx1 = spend(&[outpoint(&coinbase1, 0)], &[20, 30]);
+        let tx2 = spend(&[outpoint(&tx1, 1)], &[25]);
+        let block2 = block(vec![coinbase2.clone(), tx1.clone(), tx2.clone()]);
+        let undo2 = utxos.connect_block(&block2, 2).unwrap();
+
+        assert_eq!(3, utxos.len());
+        assert!(!utxos.contains(&outpoint(&coinbase1, 0)));
+        assert!(!utxos.contains(&outpoint(&tx1, 1)));
+        assert_eq!(
+            Some(&Coin { output: tx1.outputs[0].clone(), height: 2, is_coinbase: false }),
+            utxos.get(&outpoint(&tx1, 0))
+        );
+        assert!(utxos.get(&outpoint(&coinbase2, 0)).unwrap().is_coinbase);
+        assert_eq!(2, undo2.spent.len());
+
+        utxos.disconnect_block(&block2, &undo2).unwrap();
+        assert_eq!(1, utxos.len());
+        assert_eq!(
+            Some(&Coin { output: coinbase1.outputs[0].clone(), height: 1, is_coinbase: true }),
+            utxos.get(&outpoint(&coinbase1, 0))
+        );
+
+        utxos.disconnect_block(&block1, &undo1).unwrap();
+        assert!(utxos.is_empty());
+    }
+
+    #[test]
+    fn missing_coin_test() {
+        let mut utxos = new();
+
+        let coinbase1 = coinbase(b"1");
+        utxos.connect_block(&block(vec![coinbase1.clone()]), 1).unwrap();
+
+        // the second transaction spends the same output again.
+        let tx1 = spend(&[outpoint(&coinbase1, 0)], &[50]);
+        let tx2 = spend(&[outpoint(&tx1, 0), outpoint(&coinbase1, 0)], &[50]);
+        let block2 = block(vec![coinbase(b"2"), tx1, tx2]);
+
+        assert_eq!(Err(Error::MissingCoin(outpoint(&coinbase1, 0))), utxos.connect_block(&block2, 2));
+
+        // nothing changed.
+        assert_eq!(1, utxos.len());
+        assert!(utxos.contains(&outpoint(&coinbase1, 0)));
+    }
+
+    #[test]
+    fn bad_undo_test() {
+        let mut utxos = new();
+
+        let coinbase1 = coinbase(b"1");
+        utxos.connect_block(&block(vec![coinbase1.clone()]), 1).unwrap();
+
+        let block2 = block(vec![coinbase(b"2"), spend(&[outpoint(&coinbase1, 0)], &[50])]);
+        utxos.connect_block(&block2, 2).unwrap();
+
+        assert_eq!(Err(Error::BadUndo), utxos.disconnect_block(&block2, &BlockUndo::default()));
+    }
+
+    #[test]
+    fn find_by_script_test() {
+        let mut utxos = new();
+
+        let coinbase1 = coinbase(b"1");
+        let tx1 = spend(&[outpoint(&coinbase1, 0)], &[20, 30]);
+        utxos.connect_block(&block(vec![coinbase1]), 1).unwrap();
+        utxos.connect_block(&block(vec![coinbase(b"2"), tx1.clone()]), 2).unwrap();
+
+        let mut found: Vec<_> = utxos.find_by_script(&p2sh())
+            .into_iter()
+            .map(|(outpoint, coin)| (outpoint.vout, coin.output.value))
+            .collect();
+        found.sort();
+
+        // the second coinbase pays the same script.
+        assert_eq!(vec![(0, 20), (0, 50 * COIN), (1, 30)], found);
+    }
+}
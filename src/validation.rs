@@ -0,0 +1,602 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+
+use crate::block::Block;
+use crate::difficulty;
+use crate::merkleroot;
+use crate::params::ChainParams;
+use crate::script::Script;
+use crate::transactions::{OutPoint, Transaction};
+use crate::u256;
+use crate::utxo::UtxoSet;
+
+// Block validation happens in two steps, like bitcoin core's CheckBlock and ConnectBlock:
+//
+// - `check_block` only looks at the block itself: proof of work, merkle root, coinbase, limits.
+// - `validate_block` also checks the block against the chain it extends: its parent, its
+//   timestamp, and the outputs its inputs spend.
+//
+// Scripts aren't verified yet.
+
+/// Maximum weight of a block, see BIP141.
+pub const MAX_BLOCK_WEIGHT: usize = 4_000_000;
+
+/// Number of satoshis in one bitcoin.
+pub const COIN: u64 = 100_000_000;
+
+/// No amount can be larger than the 21 million bitcoins, in satoshis.
+pub const MAX_MONEY: u64 = 21_000_000 * COIN;
+
+/// Number of blocks before the outputs of a coinbase can be spent.
+pub const COINBASE_MATURITY: u32 = 100;
+
+/// How far in the future a block timestamp can be, in seconds.
+pub const MAX_FUTURE_BLOCK_TIME: u32 = 2 * 60 * 60;
+
+/// Number of previous blocks the median time past is computed from.
+pub const MEDIAN_TIME_SPAN: usize = 11;
+
+/// The mainnet blocks, by height and hash, allowed to break BIP30.
+const BIP30_EXCEPTIONS: [(u32, u256); 2] = [
+    (91842, u256::from_words(0x00000000000a4d0a398161ffc163c503, 0x763b1f4360639393e0e4c8e300e0caec)),
+    (91880, u256::from_words(0x00000000000743f190a18c5577a3c2d2, 0xa1f610ae9601ac046a38084ccb7cd721)),
+];
+
+/// What the block is validated against: the chain up to its parent.
+#[derive(Clone, Debug)]
+pub struct BlockContext {
+    /// Height of the block being validated.
+    pub height: u32,
+    pub prev_block_hash: u256,
+    /// The bits required at this height, see `difficulty::retarget`.
+    pub bits: u32,
+    /// The new coins the coinbase can claim at this height, see `block_subsidy`.
+    pub subsidy: u64,
+    pub median_time_past: u32,
+    /// The current time, as a unix timestamp.
+    pub now: u32,
+    /// Whether the coinbase must start with the block height, see BIP34.
+    pub enforce_bip34: bool,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum BlockError {
+    /// The block hash is above the target of its bits.
+    HighHash,
+    BadMerkleRoot,
+    BadWitnessCommitment,
+    NoTransactions,
+    FirstTransactionNotCoinbase,
+    /// A coinbase transaction anywhere else than first in the block.
+    MultipleCoinbases,
+    /// The coinbase scriptSig must be between 2 and 100 bytes.
+    BadCoinbaseLength(usize),
+    /// The coinbase scriptSig doesn't start with the height of the block, see BIP34.
+    BadCoinbaseHeight,
+    Overweight(usize),
+    DuplicateTransaction(u256),
+    /// A transaction without inputs or without outputs.
+    EmptyTransaction(u256),
+    /// An output value, or the sum of the output values of a transaction, above `MAX_MONEY`.
+    ValueOutOfRange(u256),
+    /// The same output is spent twice in the block.
+    DoubleSpend(OutPoint),
+    BadPrevBlock,
+    /// The bits aren't the ones required at this height.
+    BadBits(u32),
+    /// The timestamp isn't after the median time past.
+    TimeTooOld,
+    TimeTooNew,
+    MissingInput(OutPoint),
+    /// A transaction creates an output that is still unspent, see BIP30.
+    OverwritesUnspent(OutPoint),
+    ImmatureCoinbaseSpend(OutPoint),
+    /// The transaction spends more than the value of its inputs.
+    InputsBelowOutputs(u256),
+    /// The coinbase claims more than the subsidy and the fees of the block.
+    BadCoinbaseAmount { value: u64, max: u64 },
+}
+
+/// Checks what can be checked without the rest of the chain.
+pub fn check_block(block: &Block) -> Result<(), BlockError> {
+    if block.hash() > difficulty::bits_to_target(block.header.bits) {
+        return Err(BlockError::HighHash)
+    };
+
+    if block.transactions.is_empty() {
+        return Err(BlockError::NoTransactions)
+    };
+
+    if hex::encode(block.header.merkle_root.to_le_bytes()) != merkleroot::from_transactions(&block.transactions) {
+        return Err(BlockError::BadMerkleRoot)
+    };
+
+    let weight = block.weight();
+    if weight > MAX_BLOCK_WEIGHT {
+        return Err(BlockError::Overweight(weight))
+    };
+
+    let coinbase = &block.transactions[0];
+    if !coinbase.is_coinbase() {
+        return Err(BlockError::FirstTransactionNotCoinbase)
+    };
+
+    let script_sig_len = coinbase.inputs[0].script_sig.len();
+    if !(2..=100).contains(&script_sig_len) {
+        return Err(BlockError::BadCoinbaseLength(script_sig_len))
+    };
+
+    if block.transactions[1..].iter().any(|tx| tx.is_coinbase()) {
+        return Err(BlockError::MultipleCoinbases)
+    };
+
+    let mut txids = HashSet::new();
+    let mut spent = HashSet::new();
+
+    for tx in &block.transactions {
+        let txid = tx.txid();
+
+        if !txids.insert(txid) {
+            return Err(BlockError::DuplicateTransaction(txid))
+        };
+
+        check_transaction(tx)?;
+
+        if tx.is_coinbase() {
+            continue
+        };
+
+        for txin in &tx.inputs {
+            if !spent.insert(txin.outpoint()) {
+                return Err(BlockError::DoubleSpend(txin.outpoint()))
+            };
+        };
+    };
+
+    if !block.check_witness_commitment() {
+        return Err(BlockError::BadWitnessCommitment)
+    };
+
+    Ok(())
+}
+
+/// Fully validates a block extending the chain described by `context`, whose unspent outputs are
+/// `utxos`.
+pub fn validate_block(block: &Block, context: &BlockContext, utxos: &UtxoSet) -> Result<(), BlockError> {
+    check_block(block)?;
+
+    if block.header.prev_block_hash != context.prev_block_hash {
+        return Err(BlockError::BadPrevBlock)
+    };
+
+    if block.header.bits != context.bits {
+        return Err(BlockError::BadBits(block.header.bits))
+    };
+
+    if block.header.time <= context.median_time_past {
+        return Err(BlockError::TimeTooOld)
+    };
+
+    if block.header.time > context.now.saturating_add(MAX_FUTURE_BLOCK_TIME) {
+        return Err(BlockError::TimeTooNew)
+    };
+
+    // https://github.com/bitcoin/bips/blob/master/bip-0034.mediawiki
+    let script_sig = block.transactions[0].inputs[0].script_sig.as_bytes();
+    if context.enforce_bip34 && !script_sig.starts_with(coinbase_height(context.height).as_bytes()) {
+        return Err(BlockError::BadCoinbaseHeight)
+    };
+
+    // https://github.com/bitcoin/bips/blob/master/bip-0030.mediawiki
+    //
+    // A transaction with the txid of a previous one would overwrite its unspent outputs, which
+    // would then be lost when the block is disconnected. Once BIP34 puts the height in the
+    // coinbases, txids can't repeat anymore. Two old mainnet blocks did overwrite coinbases.
+    if !context.enforce_bip34 && !BIP30_EXCEPTIONS.contains(&(context.height, block.hash())) {
+        for tx in &block.transactions {
+            let txid = tx.txid();
+
+            for vout in 0..tx.outputs.len() as u32 {
+                if utxos.contains(&OutPoint { txid, vout }) {
+                    return Err(BlockError::OverwritesUnspent(OutPoint { txid, vout }))
+                };
+            };
+        };
+    };
+
+    // the outputs created by the previous transactions of the block: (value, is coinbase).
+    let mut created: HashMap<OutPoint, (u64, bool)> = HashMap::new();
+    let mut fees: u64 = 0;
+
+    for tx in &block.transactions {
+        let txid = tx.txid();
+
+        if !tx.is_coinbase() {
+            let mut value_in: u64 = 0;
+
+            for txin in &tx.inputs {
+                let outpoint = txin.outpoint();
+
+                let (value, height, is_coinbase) = match (created.get(&outpoint), utxos.get(&outpoint)) {
+                    (Some((value, is_coinbase)), _) => (*value, context.height, *is_coinbase),
+                    (None, Some(coin)) => (coin.output.value, coin.height, coin.is_coinbase),
+                    (None, None) => return Err(BlockError::MissingInput(outpoint)),
+                };
+
+                if is_coinbase && context.height.saturating_sub(height) < COINBASE_MATURITY {
+                    return Err(BlockError::ImmatureCoinbaseSpend(outpoint))
+                };
+
+                value_in += value;
+                if value_in > MAX_MONEY {
+                    return Err(BlockError::ValueOutOfRange(txid))
+                };
+            };
+
+            if value_in < value_out(tx) {
+                return Err(BlockError::InputsBelowOutputs(txid))
+            };
+
+            fees += value_in - value_out(tx);
+        };
+
+        for (vout, output) in tx.outputs.iter().enumerate() {
+            created.insert(OutPoint { txid, vout: vout as u32 }, (output.value, tx.is_coinbase()));
+        };
+    };
+
+    let coinbase_value = value_out(&block.transactions[0]);
+    if coinbase_value > context.subsidy + fees {
+        return Err(BlockError::BadCoinbaseAmount { value: coinbase_value, max: context.subsidy + fees })
+    };
+
+    Ok(())
+}
+
+/// The new coins a block can create: 50 bitcoins, halved every `subsidy_halving_interval` blocks.
+pub fn block_subsidy(height: u32, params: &ChainParams) -> u64 {
+    let halvings = height / params.subsidy_halving_interval;
+
+    // the shift of a u64 by 64 bits or more is undefined.
+    if halvings >= 64 {
+        return 0
+    };
+
+    (50 * COIN) >> halvings
+}
+
+/// The push of the block height a coinbase scriptSig starts with, see BIP34.
+pub fn coinbase_height(height: u32) -> Script {
+    let mut script = Script::new();
+    script.push_int(height as i64);
+    script
+}
+
+/// The median of the timestamps of the last `MEDIAN_TIME_SPAN` blocks. `times` is in chain
+/// order, and can have fewer timestamps near genesis.
+pub fn median_time_past(times: &[u32]) -> u32 {
+    let mut times = times[times.len().saturating_sub(MEDIAN_TIME_SPAN)..].to_vec();
+    times.sort();
+
+    times[times.len() / 2]
+}
+
+/// Checks what can be checked on the transaction alone: it has inputs and outputs, and its values
+/// are in range.
+pub fn check_transaction(tx: &Transaction) -> Result<(), BlockError> {
+    if tx.inputs.is_empty() || tx.outputs.is_empty() {
+        return Err(BlockError::EmptyTransaction(tx.txid()))
+    };
+
+    let mut total: u64 = 0;
+    for output in &tx.outputs {
+        total = total.saturating_add(output.value);
+
+        if output.value > MAX_MONEY || total > MAX_MONEY {
+            return Err(BlockError::ValueOutOfRange(tx.txid()))
+        };
+    };
+
+    Ok(())
+}
+
+/// Sum of the output values, once checked by `check_transaction`.
+fn value_out(tx: &Transaction) -> u64 {
+    tx.outputs.iter().map(|output| output.value).sum()
+}
+
+impl fmt::Display for BlockError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let hex = |hash: &u256| hex::encode(hash.to_be_bytes());
+
+        match self {
+            BlockError::HighHash => write!(f, "hash above the target"),
+            BlockError::BadMerkleRoot => write!(f, "merkle root mismatch"),
+            BlockError::BadWitnessCommitment => write!(f, "witness commitment mismatch"),
+            BlockError::NoTransactions => write!(f, "no transactions"),
+            BlockError::FirstTransactionNotCoinbase => write!(f, "first transaction isn't a coinbase"),
+            BlockError::MultipleCoinbases => write!(f, "more than one coinbase"),
+            BlockError::BadCoinbaseLength(len) => write!(f, "coinbase scriptSig of {} bytes", len),
+            BlockError::BadCoinbaseHeight => write!(f, "coinbase doesn't start with the block height"),
+            BlockError::Overweight(weight) => write!(f, "weight of {} above the limit", weight),
+            BlockError::DuplicateTransaction(txid) => write!(f, "duplicate transaction {}", hex(txid)),
+            BlockError::EmptyTransaction(txid) => write!(f, "transaction {} without inputs or outputs", hex(txid)),
+            BlockError::ValueOutOfRange(txid) => write!(f, "transaction {} value out of range", hex(txid)),
+            BlockError::DoubleSpend(outpoint) => write!(f, "output {}:{} spent twice", hex(&outpoint.txid), outpoint.vout),
+            BlockError::BadPrevBlock => write!(f, "previous block isn't the tip"),
+            BlockError::BadBits(bits) => write!(f, "bits {:#010x} aren't the required difficulty", bits),
+            BlockError::TimeTooOld => write!(f, "timestamp not after the median time past"),
+            BlockError::TimeTooNew => write!(f, "timestamp too far in the future"),
+            BlockError::MissingInput(outpoint) => write!(f, "missing or spent output {}:{}", hex(&outpoint.txid), outpoint.vout),
+            BlockError::OverwritesUnspent(outpoint) => write!(f, "output {}:{} already unspent", hex(&outpoint.txid), outpoint.vout),
+            BlockError::ImmatureCoinbaseSpend(outpoint) => write!(f, "immature coinbase output {}:{} spent", hex(&outpoint.txid), outpoint.vout),
+            BlockError::InputsBelowOutputs(txid) => write!(f, "transaction {} spends more than its inputs", hex(txid)),
+            BlockError::BadCoinbaseAmount { value, max } => write!(f, "coinbase pays {}, more than the {} allowed", value, max),
+        }
+    }
+}
+
+impl std::error::Error for BlockError {}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::block::{self, Header};
+    use crate::script::Script;
+    use crate::transactions::TxOut;
+    use crate::transactions::test::{coinbase, spend};
+    use crate::utxo;
+
+    const EASY_BITS: u32 = 0x207fffff;
+
+    /// A block with a valid proof of work and merkle root, on top of genesis.
+    fn mine(transactions: Vec<Transaction>) -> Block {
+        let merkle_root = hex::decode(merkleroot::from_transactions(&transactions)).unwrap();
+        let mut block = Block {
+            header: Header {
+                version: 1,
+                prev_block_hash: block::genesis().hash(),
+                merkle_root: u256::from_le_bytes(merkle_root.try_into().unwrap()),
+                time: block::genesis().header.time + 600,
+                bits: EASY_BITS,
+                nounce: 0,
+            },
+            transactions,
+        };
+
+        while block.hash() > difficulty::bits_to_target(EASY_BITS) {
+            block.header.nounce += 1;
+        };
+
+        block
+    }
+
+    fn context(height: u32) -> BlockContext {
+        let time = block::genesis().header.time;
+        BlockContext {
+            height,
+            prev_block_hash: block::genesis().hash(),
+            bits: EASY_BITS,
+            subsidy: 50 * COIN,
+            median_time_past: time,
+            now: time + 600,
+            enforce_bip34: false,
+        }
+    }
+
+    /// A UTXO set with a coinbase output of 50 btc mined at height 1.
+    fn utxos_with_coinbase() -> (UtxoSet, OutPoint) {
+        let mut utxos = utxo::new();
+        let coinbase = coinbase(b"funding");
+        utxos.connect_block(&mine(vec![coinbase.clone()]), 1).unwrap();
+
+        (utxos, OutPoint { txid: coinbase.txid(), vout: 0 })
+    }
+
+    #[test]
+    fn check_real_blocks_test() {
+        assert_eq!(Ok(()), check_block(&block::genesis()));
+
+        let bytes = include_bytes!("../test_data/mainnet_block_000000000000000000000c835b2adcaedc20fdf6ee440009c249452c726dafae.raw");
+        assert_eq!(Ok(()), check_block(&Block::from_bytes(bytes).unwrap()));
+
+        let bytes = include_bytes!("../test_data/testnet_block_000000000000045e0b1660b6445b5e5c5ab63c9a4f956be7e1e69be04fa4497b.raw");
+        assert_eq!(Ok(()), check_block(&Block::from_bytes(bytes).unwrap()));
+    }
+
+    #[test]
+    fn check_proof_of_work_test() {
+        let mut block = block::genesis();
+        block.header.nounce += 1;
+
+        assert_eq!(Err(BlockError::HighHash), check_block(&block));
+    }
+
+    #[test]
+    fn check_merkle_root_test() {
+        let mut block = mine(vec![coinbase(b"rcoin")]);
+        block.transactions[0].outputs[0].value += 1;
+
+        assert_eq!(Err(BlockError::BadMerkleRoot), check_block(&block));
+    }
+
+    #[test]
+    fn check_coinbase_test() {
+        let outpoint = OutPoint { txid: u256::new(1), vout: 0 };
+
+        let block = mine(vec![spend(&[outpoint], &[1])]);
+        assert_eq!(Err(BlockError::FirstTransactionNotCoinbase), check_block(&block));
+
+        let block = mine(vec![coinbase(b"rcoin"), coinbase(b"again")]);
+        assert_eq!(Err(BlockError::MultipleCoinbases), check_block(&block));
+
+        let block = mine(vec![coinbase(b"r")]);
+        assert_eq!(Err(BlockError::BadCoinbaseLength(1)), check_block(&block));
+    }
+
+    #[test]
+    fn check_transactions_test() {
+        let outpoint = OutPoint { txid: u256::new(1), vout: 0 };
+
+        let tx = spend(&[outpoint], &[1]);
+        let block = mine(vec![coinbase(b"rcoin"), tx.clone(), tx.clone()]);
+        assert_eq!(Err(BlockError::DuplicateTransaction(tx.txid())), check_block(&block));
+
+        let block = mine(vec![coinbase(b"rcoin"), spend(&[outpoint], &[1]), spend(&[outpoint], &[2])]);
+        assert_eq!(Err(BlockError::DoubleSpend(outpoint)), check_block(&block));
+
+        let block = mine(vec![coinbase(b"rcoin"), spend(&[outpoint, outpoint], &[1])]);
+        assert_eq!(Err(BlockError::DoubleSpend(outpoint)), check_block(&block));
+
+        let tx = spend(&[outpoint], &[]);
+        let block = mine(vec![coinbase(b"rcoin"), tx.clone()]);
+        assert_eq!(Err(BlockError::EmptyTransaction(tx.txid())), check_block(&block));
+
+        let tx = spend(&[outpoint], &[MAX_MONEY, 1]);
+        let block = mine(vec![coinbase(b"rcoin"), tx.clone()]);
+        assert_eq!(Err(BlockError::ValueOutOfRange(tx.txid())), check_block(&block));
+    }
+
+    #[test]
+    fn check_witness_commitment_test() {
+        let mut tx = spend(&[OutPoint { txid: u256::new(1), vout: 0 }], &[1]);
+        tx.inputs[0].witness = vec![vec![0xab]];
+
+        let block = mine(vec![coinbase(b"rcoin"), tx]);
+        assert_eq!(Err(BlockError::BadWitnessCommitment), check_block(&block));
+    }
+
+    #[test]
+    fn validate_context_test() {
+        let utxos = utxo::new();
+        let block = mine(vec![coinbase(b"rcoin")]);
+        assert_eq!(Ok(()), validate_block(&block, &context(1), &utxos));
+
+        let other = BlockContext { prev_block_hash: u256::new(1), ..context(1) };
+        assert_eq!(Err(BlockError::BadPrevBlock), validate_block(&block, &other, &utxos));
+
+        let other = BlockContext { bits: 0x1d00ffff, ..context(1) };
+        assert_eq!(Err(BlockError::BadBits(EASY_BITS)), validate_block(&block, &other, &utxos));
+
+        let other = BlockContext { median_time_past: block.header.time, ..context(1) };
+        assert_eq!(Err(BlockError::TimeTooOld), validate_block(&block, &other, &utxos));
+
+        let other = BlockContext { now: block.header.time - MAX_FUTURE_BLOCK_TIME - 1, ..context(1) };
+        assert_eq!(Err(BlockError::TimeTooNew), validate_block(&block, &other, &utxos));
+    }
+
+    #[test]
+    fn validate_coinbase_height_test() {
+        let utxos = utxo::new();
+        let bip34 = BlockContext { enforce_bip34: true, ..context(227931) };
+
+        // the coinbase of block 227931, the first one enforcing BIP34 on mainnet.
+        let block = mine(vec![coinbase(&hex::decode("035b7a03062f503253482f").unwrap())]);
+        assert_eq!(Ok(()), validate_block(&block, &bip34, &utxos));
+
+        let block = mine(vec![coinbase(&hex::decode("035c7a03062f503253482f").unwrap())]);
+        assert_eq!(Err(BlockError::BadCoinbaseHeight), validate_block(&block, &bip34, &utxos));
+
+        // small heights are pushed with OP_1 to OP_16.
+        let block = mine(vec![coinbase(&[0x51, 0x00])]);
+        assert_eq!(Ok(()), validate_block(&block, &BlockContext { enforce_bip34: true, ..context(1) }, &utxos));
+    }
+
+    #[test]
+    fn validate_inputs_test() {
+        let (utxos, outpoint) = utxos_with_coinbase();
+
+        // spends the coinbase, then an output of the same block.
+        let tx1 = spend(&[outpoint], &[20 * COIN, 30 * COIN]);
+        let tx2 = spend(&[OutPoint { txid: tx1.txid(), vout: 1 }], &[30 * COIN]);
+        let block = mine(vec![coinbase(b"rcoin"), tx1, tx2]);
+        assert_eq!(Ok(()), validate_block(&block, &context(1 + COINBASE_MATURITY), &utxos));
+
+        assert_eq!(
+            Err(BlockError::ImmatureCoinbaseSpend(outpoint)),
+            validate_block(&block, &context(COINBASE_MATURITY), &utxos)
+        );
+
+        let missing = OutPoint { txid: u256::new(1), vout: 0 };
+        let block = mine(vec![coinbase(b"rcoin"), spend(&[missing], &[1])]);
+        assert_eq!(Err(BlockError::MissingInput(missing)), validate_block(&block, &context(200), &utxos));
+
+        let tx = spend(&[outpoint], &[50 * COIN + 1]);
+        let block = mine(vec![coinbase(b"rcoin"), tx.clone()]);
+        assert_eq!(Err(BlockError::InputsBelowOutputs(tx.txid())), validate_block(&block, &context(200), &utxos));
+    }
+
+    #[test]
+    fn validate_bip30_test() {
+        let (utxos, outpoint) = utxos_with_coinbase();
+
+        // the coinbase is the same as the unspent one, even if the block spends it first.
+        let block = mine(vec![coinbase(b"funding")]);
+        assert_eq!(Err(BlockError::OverwritesUnspent(outpoint)), validate_block(&block, &context(200), &utxos));
+
+        let block = mine(vec![coinbase(b"funding"), spend(&[outpoint], &[50 * COIN])]);
+        assert_eq!(Err(BlockError::OverwritesUnspent(outpoint)), validate_block(&block, &context(200), &utxos));
+    }
+
+    #[test]
+    fn validate_coinbase_amount_test() {
+        let (utxos, outpoint) = utxos_with_coinbase();
+
+        // the coinbase can claim the subsidy and the 1 btc of fees.
+        let fee_tx = spend(&[outpoint], &[49 * COIN]);
+        let mut claim = coinbase(b"rcoin");
+        claim.outputs[0].value = 51 * COIN;
+
+        let block = mine(vec![claim.clone(), fee_tx.clone()]);
+        assert_eq!(Ok(()), validate_block(&block, &context(200), &utxos));
+
+        claim.outputs.push(TxOut { value: 1, script_pub_key: Script::new() });
+        let block = mine(vec![claim, fee_tx]);
+        assert_eq!(
+            Err(BlockError::BadCoinbaseAmount { value: 51 * COIN + 1, max: 51 * COIN }),
+            validate_block(&block, &context(200), &utxos)
+        );
+
+        let context = BlockContext { subsidy: 25 * COIN, ..context(200) };
+        let block = mine(vec![coinbase(b"rcoin")]);
+        assert_eq!(
+            Err(BlockError::BadCoinbaseAmount { value: 50 * COIN, max: 25 * COIN }),
+            validate_block(&block, &context, &utxos)
+        );
+    }
+
+    #[test]
+    fn block_subsidy_test() {
+        let params = ChainParams::mainnet();
+
+        assert_eq!(50 * COIN, block_subsidy(0, &params));
+        assert_eq!(50 * COIN, block_subsidy(209_999, &params));
+        assert_eq!(25 * COIN, block_subsidy(210_000, &params));
+        assert_eq!(312_500_000, block_subsidy(840_000, &params));
+        // the last satoshi is created by the 33rd halving.
+        assert_eq!(1, block_subsidy(32 * 210_000, &params));
+        assert_eq!(0, block_subsidy(33 * 210_000, &params));
+        assert_eq!(0, block_subsidy(64 * 210_000, &params));
+
+        // regtest halves every 150 blocks.
+        assert_eq!(25 * COIN, block_subsidy(150, &ChainParams::regtest()));
+    }
+
+    #[test]
+    fn total_supply_test() {
+        let params = ChainParams::mainnet();
+        let supply: u64 = (0..64).map(|halving| block_subsidy(halving * 210_000, &params) * 210_000).sum();
+
+        // a bit less than 21 million because of the rounding down of the subsidy.
+        assert_eq!(2_099_999_997_690_000, supply);
+        assert!(supply <= MAX_MONEY);
+    }
+
+    #[test]
+    fn median_time_past_test() {
+        assert_eq!(5, median_time_past(&[5]));
+        assert_eq!(2, median_time_past(&[3, 1, 2]));
+        // only the last 11 timestamps count.
+        assert_eq!(7, median_time_past(&[100, 100, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12]));
+    }
+}